@@ -0,0 +1,450 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{Ride, RouteDirection, Stop},
+    events::Event,
+    gtfs::RealtimeFeed,
+    history::HistoryEntry,
+    metrics::{self, METRICS},
+    pipeline::Pipeline,
+    services::{Adherence, FetchService, Headway, StopVisit, Vehicle},
+};
+
+#[derive(Clone)]
+struct ApiState {
+    fetch_service: Arc<FetchService>,
+    pipeline: Arc<Pipeline>,
+    realtime_feed: Arc<RealtimeFeed>,
+}
+
+#[derive(Debug, Serialize)]
+struct VehicleDetails {
+    #[serde(flatten)]
+    vehicle: Vehicle,
+    arrivals: Vec<StopArrival>,
+}
+
+#[derive(Debug, Serialize)]
+struct StopArrival {
+    stop: String,
+    time: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+struct Period {
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct StopDetails {
+    #[serde(flatten)]
+    stop: Stop,
+    arrivals: Vec<VehicleArrival>,
+}
+
+#[derive(Debug, Serialize)]
+struct VehicleArrival {
+    car_license: String,
+    time: NaiveDateTime,
+}
+
+pub fn router(
+    fetch_service: Arc<FetchService>,
+    pipeline: Arc<Pipeline>,
+    realtime_feed: Arc<RealtimeFeed>,
+) -> Router {
+    Router::new()
+        .route("/vehicles", get(vehicles))
+        .route("/vehicles/{license}", get(vehicle))
+        .route("/vehicles/{license}/history", get(history))
+        .route("/stops", get(stops))
+        .route("/stops/{id}", get(stop))
+        .route("/stops/{id}/adherence", get(stop_adherence))
+        .route("/stops/{id}/visits", get(stop_visits))
+        .route("/adherence", get(adherence))
+        .route("/headways", get(headways))
+        .route("/rides/{position}", get(rides))
+        .route("/metrics", get(metrics))
+        .route("/gtfs-rt/vehicle-positions", get(vehicle_positions))
+        .route("/gtfs-rt/trip-updates", get(trip_updates))
+        .with_state(ApiState {
+            fetch_service,
+            pipeline,
+            realtime_feed,
+        })
+}
+
+pub async fn serve(addr: &str, router: Router) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    Event::ApiListening {
+        addr: listener.local_addr()?.to_string(),
+    }
+    .emit();
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn vehicles(State(state): State<ApiState>) -> Json<Vec<Vehicle>> {
+    Json(state.pipeline.vehicle_service.all())
+}
+
+async fn vehicle(
+    State(state): State<ApiState>,
+    Path(license): Path<String>,
+) -> Result<Json<VehicleDetails>, StatusCode> {
+    let vehicle = state
+        .pipeline
+        .vehicle_service
+        .get(&license)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let arrivals = state
+        .pipeline
+        .eta_service
+        .prediction(&license)
+        .map(|p| {
+            p.arrivals
+                .into_iter()
+                .map(|(stop, time)| StopArrival {
+                    stop: stop.name,
+                    time,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Json(VehicleDetails { vehicle, arrivals }))
+}
+
+async fn history(
+    State(state): State<ApiState>,
+    Path(license): Path<String>,
+    Query(period): Query<Period>,
+) -> Result<Json<Vec<HistoryEntry>>, StatusCode> {
+    let history = state
+        .pipeline
+        .history
+        .clone()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    tokio::task::spawn_blocking(move || history.history(&license, period.from, period.to))
+        .await
+        .map_err(anyhow::Error::from)
+        .flatten()
+        .map(Json)
+        .map_err(|err| {
+            Event::failed("query history", &err).emit();
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+async fn stops(State(state): State<ApiState>) -> Json<Vec<Stop>> {
+    Json(state.fetch_service.stops())
+}
+
+async fn stop(
+    State(state): State<ApiState>,
+    Path(id): Path<usize>,
+) -> Result<Json<StopDetails>, StatusCode> {
+    let stop = state
+        .fetch_service
+        .stops()
+        .into_iter()
+        .find(|s| s.unique_id == Some(id))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let arrivals = state
+        .pipeline
+        .eta_service
+        .eta(&stop.name, RouteDirection::from(stop.route_direction))
+        .into_iter()
+        .map(|(car_license, time)| VehicleArrival { car_license, time })
+        .collect();
+
+    Ok(Json(StopDetails { stop, arrivals }))
+}
+
+async fn stop_adherence(
+    State(state): State<ApiState>,
+    Path(id): Path<usize>,
+) -> Result<Json<Vec<Adherence>>, StatusCode> {
+    let stop = state
+        .fetch_service
+        .stops()
+        .into_iter()
+        .find(|s| s.unique_id == Some(id))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(state.pipeline.adherence_service.history(
+        RouteDirection::from(stop.route_direction),
+        &stop.name,
+    )))
+}
+
+/// Buses that have stood at the stop within the period, with the dwell times.
+async fn stop_visits(
+    State(state): State<ApiState>,
+    Path(id): Path<usize>,
+    Query(period): Query<Period>,
+) -> Result<Json<Vec<StopVisit>>, StatusCode> {
+    let history = state
+        .pipeline
+        .history
+        .clone()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let stop = state
+        .fetch_service
+        .stops()
+        .into_iter()
+        .find(|s| s.unique_id == Some(id))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    tokio::task::spawn_blocking(move || history.visits(&stop.name, period.from, period.to))
+        .await
+        .map_err(anyhow::Error::from)
+        .flatten()
+        .map(Json)
+        .map_err(|err| {
+            Event::failed("query stop visits", &err).emit();
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Current schedule adherence of all buses, the latest first.
+async fn adherence(State(state): State<ApiState>) -> Json<Vec<Adherence>> {
+    Json(state.pipeline.adherence_service.all())
+}
+
+/// Headways of the buses behind another one, bunching and gaps included.
+async fn headways(State(state): State<ApiState>) -> Json<Vec<Headway>> {
+    Json(state.pipeline.headway_service.all())
+}
+
+async fn rides(
+    State(state): State<ApiState>,
+    Path(position): Path<String>,
+) -> Result<Json<Vec<Ride>>, StatusCode> {
+    let rides = state.pipeline.ride_service.rides(&position);
+    if rides.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(rides))
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        METRICS.render(),
+    )
+}
+
+const PROTOBUF: [(header::HeaderName, &str); 1] =
+    [(header::CONTENT_TYPE, "application/x-protobuf")];
+
+async fn vehicle_positions(State(state): State<ApiState>) -> impl IntoResponse {
+    (PROTOBUF, state.realtime_feed.vehicle_positions())
+}
+
+async fn trip_updates(State(state): State<ApiState>) -> impl IntoResponse {
+    (PROTOBUF, state.realtime_feed.trip_updates())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use chrono::TimeDelta;
+    use rstest::rstest;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::{
+        config::{HeadwayConfig, OffRouteConfig, StopConfig},
+        history::History,
+    };
+
+    use super::*;
+
+    const LOCATION: &str = r#"{"deviceno":"0088007439","lat":"8.089848","lng":"98.313305","state":1,"speed":38,"direction":160.2,"altitude":12,"dateTime":"2024-03-20 15:10:05","vid":246,"carlicense":"10-1152","groupName":"Phuket Smart Bus"}"#;
+
+    fn sut() -> Router {
+        let fetch_service = Arc::new(FetchService::for_tests());
+        let pipeline = Arc::new(
+            Pipeline::new(
+                &fetch_service,
+                OffRouteConfig::default(),
+                StopConfig::default(),
+                HeadwayConfig::default(),
+            )
+            .with_history(Arc::new(History::in_memory(None).unwrap())),
+        );
+        pipeline.process_location_update(LOCATION);
+        pipeline.flush_history();
+        let realtime_feed = Arc::new(RealtimeFeed::new(&pipeline, TimeDelta::MAX));
+        realtime_feed.refresh();
+        router(fetch_service, pipeline, realtime_feed)
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Value) {
+        let response = sut()
+            .oneshot(axum::http::Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn vehicles() {
+        let (status, body) = get("/vehicles").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, body.as_array().unwrap().len());
+        assert_eq!("10-1152", body[0]["car_license"]);
+        assert_eq!("Bus7", body[0]["operate_position"]);
+        assert_eq!("Rawai", body[0]["direction"]);
+        assert_eq!("Phuket Airport", body[0]["previous_stop"]);
+        assert_eq!("Thalang Public Health Office", body[0]["next_stop"]);
+    }
+
+    #[tokio::test]
+    async fn vehicle() {
+        let (status, body) = get("/vehicles/10-1152").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(38, body["speed"]);
+        assert_eq!("Thalang Public Health Office", body["arrivals"][0]["stop"]);
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let (status, body) =
+            get("/vehicles/10-1152/history?from=2024-03-20T15:00:00&to=2024-03-20T16:00:00").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, body.as_array().unwrap().len());
+        assert_eq!("2024-03-20T15:10:05", body[0]["date_time"]);
+        assert_eq!("Phuket Airport", body[0]["previous_stop"]);
+    }
+
+    #[tokio::test]
+    async fn stop() {
+        let (status, body) = get("/stops/19").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Baan Khian", body["name"]);
+        assert_eq!("10-1152", body["arrivals"][0]["car_license"]);
+    }
+
+    #[tokio::test]
+    async fn stops() {
+        let (status, body) = get("/stops").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(52, body.as_array().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn adherence() {
+        let (status, body) = get("/adherence").await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.as_array().unwrap().is_empty());
+
+        let (status, body) = get("/stops/19/adherence").await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn headways() {
+        let (status, body) = get("/headways").await;
+
+        assert_eq!(StatusCode::OK, status);
+        // A single bus has no bus ahead.
+        assert!(body.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stop_visits() {
+        let (status, body) =
+            get("/stops/19/visits?from=2024-03-20T15:00:00&to=2024-03-20T16:00:00").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rides() {
+        let (status, body) = get("/rides/Bus7").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(4, body.as_array().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn gtfs_rt() {
+        let response = sut()
+            .oneshot(
+                axum::http::Request::get("/gtfs-rt/vehicle-positions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "application/x-protobuf",
+            response.headers()[header::CONTENT_TYPE]
+        );
+        assert!(!to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let response = sut()
+            .oneshot(
+                axum::http::Request::get("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("smart_bus_process_location_update_seconds_count"));
+        assert!(text.contains("smart_bus_non_operating_buses_total{reason=\"no_ride\"}"));
+    }
+
+    #[rstest]
+    #[case::vehicle("/vehicles/99-9999", StatusCode::NOT_FOUND)]
+    #[case::stop("/stops/1000", StatusCode::NOT_FOUND)]
+    #[case::stop_id("/stops/kata", StatusCode::BAD_REQUEST)]
+    #[case::stop_adherence("/stops/1000/adherence", StatusCode::NOT_FOUND)]
+    #[case::stop_visits(
+        "/stops/1000/visits?from=2024-03-20T15:00:00&to=2024-03-20T16:00:00",
+        StatusCode::NOT_FOUND
+    )]
+    #[case::rides("/rides/Bus42", StatusCode::NOT_FOUND)]
+    #[case::history_period("/vehicles/10-1152/history", StatusCode::BAD_REQUEST)]
+    #[tokio::test]
+    async fn not_found(#[case] uri: &str, #[case] expected: StatusCode) {
+        assert_eq!(expected, get(uri).await.0);
+    }
+}
@@ -0,0 +1,124 @@
+//! Local stand-in for the operator's socket.io endpoint.
+//!
+//! Every connected client receives the `sub_gps` payloads from a fixture or a recording,
+//! in their original order.
+//!
+//! Usage: `fake_socket <file> [--addr 127.0.0.1:0] [--interval-ms 500] [--repeat]`
+//!
+//! The file is JSONL, each line is either a raw `Location` object (fixture)
+//! or a `{"received_at": ..., "payload": "..."}` record written by the `record` mode.
+
+use std::{
+    env::args,
+    fs::File,
+    io::{BufRead, BufReader},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+use socketioxide::{extract::SocketRef, SocketIo};
+
+struct Options {
+    file: String,
+    addr: String,
+    interval: Duration,
+    repeat: bool,
+}
+
+impl Options {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = args().skip(1);
+        let mut options = Self {
+            file: String::new(),
+            addr: "127.0.0.1:0".to_string(),
+            interval: Duration::from_millis(500),
+            repeat: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--addr" => {
+                    options.addr = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--addr expects a value"))?;
+                }
+                "--interval-ms" => {
+                    options.interval = Duration::from_millis(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--interval-ms expects a value"))?
+                            .parse()?,
+                    );
+                }
+                "--repeat" => options.repeat = true,
+                _ if options.file.is_empty() => options.file = arg,
+                _ => bail!("unexpected argument: {arg}"),
+            }
+        }
+
+        if options.file.is_empty() {
+            bail!("Usage: fake_socket <file> [--addr 127.0.0.1:0] [--interval-ms 500] [--repeat]");
+        }
+
+        Ok(options)
+    }
+}
+
+fn load_payloads(path: &str) -> anyhow::Result<Vec<Value>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            let value: Value = serde_json::from_str(&line?)
+                .map_err(|err| anyhow!("line {}: {err:#}", index + 1))?;
+            // A recorded line keeps the payload as the raw string received from the socket.
+            value.get("payload").and_then(Value::as_str).map_or_else(
+                || Ok(value.clone()),
+                |payload| {
+                    serde_json::from_str(payload)
+                        .map_err(|err| anyhow!("line {}: {err:#}", index + 1))
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let options = Options::parse()?;
+    let payloads = Arc::new(load_payloads(&options.file)?);
+    let (interval, repeat) = (options.interval, options.repeat);
+
+    let (layer, io) = SocketIo::new_layer();
+    io.ns("/", move |socket: SocketRef| {
+        let payloads = payloads.clone();
+        async move {
+            println!("Client connected, id={}", socket.id);
+            tokio::spawn(async move {
+                loop {
+                    for payload in payloads.iter() {
+                        if socket.emit("sub_gps", payload).is_err() {
+                            println!("Client disconnected, id={}", socket.id);
+                            return;
+                        }
+                        tokio::time::sleep(interval).await;
+                    }
+                    if !repeat {
+                        println!("Fixture completed, id={}", socket.id);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind(&options.addr).await?;
+    // Integration tests read the bound address from this line.
+    println!("Listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, axum::Router::new().layer(layer)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Arrival board of a stop, live predictions where buses are tracked, advertised times
+//! otherwise.
+
+use std::fmt::Write;
+
+use chrono::{NaiveDateTime, TimeDelta};
+
+use crate::domain::{RouteDirection, Stop};
+
+/// Arrivals shown per direction.
+const ARRIVALS: usize = 5;
+/// An advertised arrival this close to a predicted one is served by the predicted bus.
+const SAME_ARRIVAL: TimeDelta = TimeDelta::minutes(15);
+/// Advertised times are shown this far ahead, past midnight late in the evening.
+const HORIZON: TimeDelta = TimeDelta::hours(12);
+
+/// The next arrival at the stop in a direction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arrival {
+    pub time: NaiveDateTime,
+    /// The bus predicted to arrive, none for the advertised times.
+    pub car_license: Option<String>,
+}
+
+/// Stops matching the English or Thai name, or the unique ID, with the stops of the same
+/// name in the other directions.
+pub fn find_stops(stops: &[Stop], query: &str) -> Vec<Stop> {
+    let query = query.trim();
+    let Some(found) = stops.iter().find(|s| {
+        s.name.eq_ignore_ascii_case(query)
+            || s.name_th == query
+            || query.parse().ok().is_some_and(|id| s.unique_id == Some(id))
+    }) else {
+        return vec![];
+    };
+    stops
+        .iter()
+        .filter(|s| s.name == found.name)
+        .cloned()
+        .collect()
+}
+
+/// The next arrivals at the stop after `now`, predicted for the tracked buses in
+/// `predictions`, advertised by the schedule of the stop otherwise.
+pub fn arrivals(
+    stop: &Stop,
+    predictions: &[(String, NaiveDateTime)],
+    now: NaiveDateTime,
+) -> Vec<Arrival> {
+    let live = predictions
+        .iter()
+        .filter(|(_, time)| *time >= now)
+        .map(|(car_license, time)| Arrival {
+            time: *time,
+            car_license: Some(car_license.clone()),
+        })
+        .collect::<Vec<_>>();
+    let advertised = stop
+        .schedule
+        .iter()
+        .map(|time| {
+            // Times earlier in the day are the ones after midnight.
+            let time = now.date().and_time(*time);
+            if time < now {
+                time + TimeDelta::days(1)
+            } else {
+                time
+            }
+        })
+        .filter(|time| *time - now <= HORIZON)
+        .filter(|time| live.iter().all(|a| (a.time - *time).abs() > SAME_ARRIVAL))
+        .map(|time| Arrival {
+            time,
+            car_license: None,
+        });
+
+    let mut arrivals = live.iter().cloned().chain(advertised).collect::<Vec<_>>();
+    arrivals.sort_by_key(|a| a.time);
+    arrivals.dedup();
+    arrivals.truncate(ARRIVALS);
+    arrivals
+}
+
+/// The board as printed to the terminal.
+pub fn render(
+    name: &str,
+    directions: &[(RouteDirection, Vec<Arrival>)],
+    now: NaiveDateTime,
+) -> String {
+    let mut board = format!("{name}\t{}\n", now.format("%H:%M"));
+    for (direction, arrivals) in directions {
+        let _ = writeln!(board, "\n{direction}");
+        if arrivals.is_empty() {
+            board.push_str("  no arrivals in the next hours\n");
+        }
+        for arrival in arrivals {
+            let _ = writeln!(
+                board,
+                "  {}\tarrives in {} min\t{}",
+                arrival.time.format("%H:%M"),
+                (arrival.time - now).num_minutes(),
+                arrival
+                    .car_license
+                    .as_ref()
+                    .map_or_else(|| "scheduled".to_string(), |c| format!("{c} live"))
+            );
+        }
+    }
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rstest::rstest;
+
+    use crate::domain::{parse_list, Terminal, TEST_STOPS};
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn stops() -> Vec<Stop> {
+        parse_list::<_, Stop>(TEST_STOPS).unwrap()
+    }
+
+    #[rstest]
+    #[case("Kata Palm")]
+    #[case(" kata palm ")]
+    #[case("กะตะ ปาล์ม")]
+    #[case("3")]
+    fn find_both_directions(#[case] query: &str) {
+        let found = find_stops(&stops(), query);
+
+        assert_eq!(
+            vec![Terminal::Airport, Terminal::Rawai],
+            found.iter().map(|s| s.route_direction).collect::<Vec<_>>()
+        );
+        assert!(found.iter().all(|s| s.name == "Kata Palm"));
+    }
+
+    #[test]
+    fn unknown_stop() {
+        assert!(find_stops(&stops(), "Kata").is_empty());
+    }
+
+    #[test]
+    fn live_before_advertised() {
+        let stops = find_stops(&stops(), "Kata Palm");
+        let stop = stops
+            .iter()
+            .find(|s| s.route_direction == Terminal::Airport)
+            .unwrap();
+        let advertised = stop
+            .schedule
+            .iter()
+            .find(|t| **t > time(16, 20).time())
+            .unwrap();
+
+        let arrivals = arrivals(
+            stop,
+            &[
+                // Serves the arrival advertised around 16:00.
+                ("10-1152".to_string(), time(16, 5)),
+                ("10-1153".to_string(), time(15, 0)),
+            ],
+            time(16, 0),
+        );
+
+        assert_eq!(
+            vec![
+                Arrival {
+                    time: time(16, 5),
+                    car_license: Some("10-1152".to_string())
+                },
+                Arrival {
+                    time: time(16, 0).date().and_time(*advertised),
+                    car_license: None
+                },
+            ],
+            arrivals[..2]
+        );
+        assert_eq!(ARRIVALS, arrivals.len());
+    }
+
+    #[test]
+    fn advertised_after_midnight() {
+        let mut stop = find_stops(&stops(), "Kata Palm").remove(0);
+        stop.schedule = vec![time(23, 40).time(), time(0, 20).time(), time(6, 0).time()];
+
+        let arrivals = arrivals(&stop, &[], time(23, 50));
+
+        assert_eq!(
+            vec![
+                time(0, 20) + TimeDelta::days(1),
+                time(6, 0) + TimeDelta::days(1)
+            ],
+            arrivals.iter().map(|a| a.time).collect::<Vec<_>>()
+        );
+    }
+}
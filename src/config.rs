@@ -1,3 +1,5 @@
+use crate::{domain::Aliases, events::LogFormat};
+
 #[derive(Debug, Clone)]
 #[cfg_attr(test, derive(Default))]
 pub struct Config {
@@ -6,12 +8,99 @@ pub struct Config {
     pub schedule_url: String,
     pub stops_url: String,
     pub update_interval: chrono::TimeDelta,
+    pub http_bind: Option<String>,
+    pub gtfs_rt_refresh: std::time::Duration,
+    pub gtfs_rt_dir: Option<String>,
+    /// Buses not seen for this long are left out of the GTFS-RT feeds.
+    pub gtfs_rt_max_age: chrono::TimeDelta,
+    pub off_route: OffRouteConfig,
+    pub stops: StopConfig,
+    pub headway: HeadwayConfig,
+    pub history_db: Option<String>,
+    pub history_retention: Option<chrono::TimeDelta>,
+    pub log_format: LogFormat,
+    pub data_source: DataSourceConfig,
+    /// Refreshes with more rejected rows in a sheet are refused, any are accepted if not set.
+    pub max_rejected_rows: Option<usize>,
+    /// Header names of the sheets by the column they stand for.
+    pub column_aliases: Aliases,
+}
+
+/// Where buses, schedule and stops are read from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DataSourceConfig {
+    /// The spreadsheet at `buses_url`, `schedule_url` and `stops_url`.
+    #[default]
+    Sheets,
+    /// JSON files in the directory, as written by the `fetch` command.
+    Directory(std::path::PathBuf),
+    /// The snapshot of `data/` built into the binary.
+    Embedded,
+}
+
+/// When a bus is considered off-route.
+#[derive(Debug, Clone, Copy)]
+pub struct OffRouteConfig {
+    /// Distance from the route, meters.
+    pub distance: f64,
+    /// How long the bus should stay beyond the distance.
+    pub duration: chrono::TimeDelta,
+}
+
+impl Default for OffRouteConfig {
+    fn default() -> Self {
+        Self {
+            distance: 150.0,
+            duration: chrono::TimeDelta::minutes(2),
+        }
+    }
+}
+
+/// When a bus is considered approaching and standing at a stop.
+#[derive(Debug, Clone, Copy)]
+pub struct StopConfig {
+    /// Distance to the next stop, meters.
+    pub approach: f64,
+    /// Distance from the stop, meters.
+    pub radius: f64,
+    /// Reported speed below which the bus is standing, km/h.
+    pub speed: u32,
+}
+
+impl Default for StopConfig {
+    fn default() -> Self {
+        Self {
+            approach: 300.0,
+            radius: 30.0,
+            speed: 5,
+        }
+    }
+}
+
+/// When buses following each other are considered bunching or leaving a gap.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadwayConfig {
+    /// Fraction of the scheduled headway below which the buses are bunching.
+    pub bunching: f64,
+    /// Multiple of the scheduled headway above which the buses leave a gap.
+    pub gap: f64,
+}
+
+impl Default for HeadwayConfig {
+    fn default() -> Self {
+        Self {
+            bunching: 0.25,
+            gap: 2.0,
+        }
+    }
 }
 
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
         let config = config::Config::builder()
             .add_source(config::File::new("config.toml", config::FileFormat::Toml))
+            // E.g. SMART_BUS_APP_SOCKET=http://127.0.0.1:3000 to use a local stand-in server.
+            .add_source(config::Environment::with_prefix("SMART_BUS"))
             .build()?;
 
         let resource = config.get_string("resource")?;
@@ -33,6 +122,97 @@ impl Config {
             ),
             update_interval: chrono::TimeDelta::try_minutes(config.get_int("update_interval_min")?)
                 .ok_or_else(|| anyhow::anyhow!("Invalid update interval"))?,
+            http_bind: optional(config.get_string("http_bind"))?,
+            gtfs_rt_refresh: std::time::Duration::from_secs(
+                optional(config.get_int("gtfs_rt_refresh_sec"))?.map_or(Ok(30), u64::try_from)?,
+            ),
+            gtfs_rt_dir: optional(config.get_string("gtfs_rt_dir"))?,
+            gtfs_rt_max_age: optional(config.get_int("gtfs_rt_max_age_sec"))?
+                .map_or_else(|| chrono::TimeDelta::minutes(5), chrono::TimeDelta::seconds),
+            off_route: OffRouteConfig {
+                distance: optional(config.get_float("off_route_distance_m"))?
+                    .unwrap_or_else(|| OffRouteConfig::default().distance),
+                duration: optional(config.get_int("off_route_duration_sec"))?.map_or_else(
+                    || OffRouteConfig::default().duration,
+                    chrono::TimeDelta::seconds,
+                ),
+            },
+            stops: StopConfig {
+                approach: optional(config.get_float("approach_distance_m"))?
+                    .unwrap_or_else(|| StopConfig::default().approach),
+                radius: optional(config.get_float("dwell_radius_m"))?
+                    .unwrap_or_else(|| StopConfig::default().radius),
+                speed: optional(config.get_int("dwell_speed_kmh"))?
+                    .map_or_else(|| Ok(StopConfig::default().speed), u32::try_from)?,
+            },
+            headway: HeadwayConfig {
+                bunching: optional(config.get_float("bunching_ratio"))?
+                    .unwrap_or_else(|| HeadwayConfig::default().bunching),
+                gap: optional(config.get_float("gap_ratio"))?
+                    .unwrap_or_else(|| HeadwayConfig::default().gap),
+            },
+            history_db: optional(config.get_string("history_db"))?,
+            history_retention: optional(config.get_int("history_retention_days"))?
+                .map(|days| {
+                    chrono::TimeDelta::try_days(days)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid history retention"))
+                })
+                .transpose()?,
+            log_format: optional(config.get_string("log_format"))?
+                .map_or(Ok(LogFormat::default()), |s| s.parse())?,
+            data_source: match optional(config.get_string("data_source"))?.as_deref() {
+                None | Some("sheets") => DataSourceConfig::Sheets,
+                Some("directory") => DataSourceConfig::Directory(
+                    optional(config.get_string("data_dir"))?
+                        .unwrap_or_else(|| "data".to_string())
+                        .into(),
+                ),
+                Some("embedded") => DataSourceConfig::Embedded,
+                Some(source) => anyhow::bail!("Unknown data source: {source}"),
+            },
+            max_rejected_rows: optional(config.get_int("max_rejected_rows"))?
+                .map(usize::try_from)
+                .transpose()?,
+            column_aliases: optional(config.get_table("column_aliases"))?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(alias, column)| Ok((alias, column.into_string()?)))
+                .collect::<anyhow::Result<_>>()?,
         })
     }
 }
+
+/// The value of a key that may be left out, failing only if it is present but malformed.
+fn optional<T>(value: Result<T, config::ConfigError>) -> anyhow::Result<Option<T>> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_keys() {
+        let config = config::Config::builder()
+            .set_override("gap_ratio", "wide")
+            .unwrap()
+            .set_override("bunching_ratio", "0.5")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Some(0.5),
+            optional(config.get_float("bunching_ratio")).unwrap()
+        );
+        assert_eq!(
+            None,
+            optional(config.get_float("approach_distance_m")).unwrap()
+        );
+        assert!(optional(config.get_float("gap_ratio")).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::{collections::HashMap, fmt::Display};
+
+use chrono::NaiveTime;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    domain::{Bus, Coordinates, Schedule, Stop, Terminal},
+    services::Snapshot,
+};
+
+/// Stops closer than this to their previous coordinates are not reported as moved.
+const MOVED_DISTANCE: f64 = 10.0;
+
+/// A change of the reference data edited in the spreadsheet.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change")]
+pub enum DataChange {
+    BusAdded {
+        car_license: String,
+        operate_position: String,
+    },
+    BusRemoved {
+        car_license: String,
+        operate_position: String,
+    },
+    BusReassigned {
+        car_license: String,
+        from: String,
+        to: String,
+    },
+    RideAdded {
+        position: String,
+        start: Terminal,
+        departure: NaiveTime,
+        arrival: NaiveTime,
+    },
+    RideRemoved {
+        position: String,
+        start: Terminal,
+        departure: NaiveTime,
+        arrival: NaiveTime,
+    },
+    RideRetimed {
+        position: String,
+        start: Terminal,
+        departure: (NaiveTime, NaiveTime),
+        arrival: (NaiveTime, NaiveTime),
+    },
+    StopAdded {
+        direction: Terminal,
+        name: String,
+        coordinates: Coordinates,
+    },
+    StopRemoved {
+        direction: Terminal,
+        name: String,
+    },
+    StopMoved {
+        direction: Terminal,
+        name: String,
+        distance: f64,
+    },
+    StopRenamed {
+        direction: Terminal,
+        from: String,
+        to: String,
+    },
+}
+
+impl Display for DataChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BusAdded {
+                car_license,
+                operate_position,
+            } => write!(f, "bus {car_license} added as {operate_position}"),
+            Self::BusRemoved {
+                car_license,
+                operate_position,
+            } => write!(f, "bus {car_license} removed from {operate_position}"),
+            Self::BusReassigned {
+                car_license,
+                from,
+                to,
+            } => write!(f, "bus {car_license} reassigned {from} => {to}"),
+            Self::RideAdded {
+                position,
+                start,
+                departure,
+                arrival,
+            } => write!(f, "ride {position} added, {departure} {start} -> {arrival}"),
+            Self::RideRemoved {
+                position,
+                start,
+                departure,
+                arrival,
+            } => write!(
+                f,
+                "ride {position} removed, {departure} {start} -> {arrival}"
+            ),
+            Self::RideRetimed {
+                position,
+                start,
+                departure,
+                arrival,
+            } => write!(
+                f,
+                "ride {position} from {start} retimed, {} -> {} => {} -> {}",
+                departure.0, arrival.0, departure.1, arrival.1
+            ),
+            Self::StopAdded {
+                direction,
+                name,
+                coordinates,
+            } => write!(f, "stop {name} to {direction} added at {coordinates}"),
+            Self::StopRemoved { direction, name } => {
+                write!(f, "stop {name} to {direction} removed")
+            }
+            Self::StopMoved {
+                direction,
+                name,
+                distance,
+            } => write!(f, "stop {name} to {direction} moved by {distance:.0}m"),
+            Self::StopRenamed {
+                direction,
+                from,
+                to,
+            } => write!(f, "stop {from} to {direction} renamed to {to}"),
+        }
+    }
+}
+
+/// Changes from the `previous` snapshot to the `next` one.
+pub fn diff(previous: &Snapshot, next: &Snapshot) -> Vec<DataChange> {
+    let mut changes = diff_buses(&previous.buses, &next.buses);
+    changes.extend(diff_rides(&previous.schedule, &next.schedule));
+    changes.extend(diff_stops(&previous.stops, &next.stops));
+    changes
+}
+
+fn diff_buses(previous: &[Bus], next: &[Bus]) -> Vec<DataChange> {
+    let before = previous
+        .iter()
+        .map(|b| (b.licence_plate_no.as_str(), b))
+        .collect::<HashMap<_, _>>();
+    let after = next
+        .iter()
+        .map(|b| (b.licence_plate_no.as_str(), b))
+        .collect::<HashMap<_, _>>();
+
+    let mut changes = vec![];
+    for bus in previous {
+        match after.get(bus.licence_plate_no.as_str()) {
+            None => changes.push(DataChange::BusRemoved {
+                car_license: bus.licence_plate_no.clone(),
+                operate_position: bus.operate_position.clone(),
+            }),
+            Some(other) if other.operate_position != bus.operate_position => {
+                changes.push(DataChange::BusReassigned {
+                    car_license: bus.licence_plate_no.clone(),
+                    from: bus.operate_position.clone(),
+                    to: other.operate_position.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    changes.extend(
+        next.iter()
+            .filter(|b| !before.contains_key(b.licence_plate_no.as_str()))
+            .map(|bus| DataChange::BusAdded {
+                car_license: bus.licence_plate_no.clone(),
+                operate_position: bus.operate_position.clone(),
+            }),
+    );
+    changes
+}
+
+type RideKey<'a> = (&'a str, Terminal);
+
+/// Departure and arrival of the rides by position and start.
+fn group_rides(schedule: &[Schedule]) -> HashMap<RideKey<'_>, Vec<(NaiveTime, NaiveTime)>> {
+    schedule
+        .iter()
+        .map(|s| ((s.position.as_str(), s.start), (s.departure, s.arrival)))
+        .sorted()
+        .into_group_map()
+}
+
+/// Rides have no identity in the sheet, so unchanged ones are matched by times first,
+/// then the rest of the same position and start are paired in the order of departure.
+fn diff_rides(previous: &[Schedule], next: &[Schedule]) -> Vec<DataChange> {
+    let mut before = group_rides(previous);
+    let mut after = group_rides(next);
+
+    let mut changes = vec![];
+    for key in before.keys().chain(after.keys()).copied().unique().sorted() {
+        let mut added = after.remove(&key).unwrap_or_default();
+        let mut removed = vec![];
+        for times in before.remove(&key).unwrap_or_default() {
+            if let Some(index) = added.iter().position(|t| *t == times) {
+                added.remove(index);
+            } else {
+                removed.push(times);
+            }
+        }
+
+        let (position, start) = key;
+        let retimed = removed.len().min(added.len());
+        for (from, to) in removed.drain(..retimed).zip(added.drain(..retimed)) {
+            changes.push(DataChange::RideRetimed {
+                position: position.to_string(),
+                start,
+                departure: (from.0, to.0),
+                arrival: (from.1, to.1),
+            });
+        }
+        for (departure, arrival) in removed {
+            changes.push(DataChange::RideRemoved {
+                position: position.to_string(),
+                start,
+                departure,
+                arrival,
+            });
+        }
+        for (departure, arrival) in added {
+            changes.push(DataChange::RideAdded {
+                position: position.to_string(),
+                start,
+                departure,
+                arrival,
+            });
+        }
+    }
+    changes
+}
+
+/// Stops are matched by name, then the rest of the same direction by their order.
+fn diff_stops(previous: &[Stop], next: &[Stop]) -> Vec<DataChange> {
+    let key = |s: &Stop| (s.route_direction, s.name.clone());
+    let before = previous
+        .iter()
+        .map(|s| (key(s), s))
+        .collect::<HashMap<_, _>>();
+    let after = next.iter().map(|s| (key(s), s)).collect::<HashMap<_, _>>();
+
+    let mut changes = vec![];
+    for stop in previous {
+        if let Some(other) = after.get(&key(stop)) {
+            moved(stop, other, &mut changes);
+        }
+    }
+
+    let mut removed = previous
+        .iter()
+        .filter(|s| !after.contains_key(&key(s)))
+        .collect::<Vec<_>>();
+    for stop in next.iter().filter(|s| !before.contains_key(&key(s))) {
+        let renamed = removed
+            .iter()
+            .position(|s| s.route_direction == stop.route_direction && s.order == stop.order);
+        if let Some(index) = renamed {
+            let previous = removed.remove(index);
+            changes.push(DataChange::StopRenamed {
+                direction: stop.route_direction,
+                from: previous.name.clone(),
+                to: stop.name.clone(),
+            });
+            moved(previous, stop, &mut changes);
+        } else {
+            changes.push(DataChange::StopAdded {
+                direction: stop.route_direction,
+                name: stop.name.clone(),
+                coordinates: stop.coordinates,
+            });
+        }
+    }
+    changes.extend(removed.into_iter().map(|stop| DataChange::StopRemoved {
+        direction: stop.route_direction,
+        name: stop.name.clone(),
+    }));
+    changes
+}
+
+fn moved(previous: &Stop, next: &Stop, changes: &mut Vec<DataChange>) {
+    let distance = previous.coordinates.distance_to(next.coordinates);
+    if distance >= MOVED_DISTANCE {
+        changes.push(DataChange::StopMoved {
+            direction: next.route_direction,
+            name: next.name.clone(),
+            distance,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use crate::{
+        domain::{Latitude, Longitude},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        (*FetchService::for_tests().snapshot()).clone()
+    }
+
+    #[test]
+    fn unchanged() {
+        assert!(diff(&snapshot(), &snapshot()).is_empty());
+    }
+
+    #[test]
+    fn buses() {
+        let previous = snapshot();
+        let mut next = previous.clone();
+        let removed = next.buses.remove(0);
+        next.buses[0].operate_position = "Bus99".to_string();
+        let reassigned = &next.buses[0];
+
+        let changes = diff(&previous, &next);
+
+        assert_eq!(
+            vec![
+                DataChange::BusRemoved {
+                    car_license: removed.licence_plate_no.clone(),
+                    operate_position: removed.operate_position.clone(),
+                },
+                DataChange::BusReassigned {
+                    car_license: reassigned.licence_plate_no.clone(),
+                    from: previous.buses[1].operate_position.clone(),
+                    to: "Bus99".to_string(),
+                },
+            ],
+            changes
+        );
+        assert_eq!(
+            vec![DataChange::BusAdded {
+                car_license: removed.licence_plate_no,
+                operate_position: removed.operate_position,
+            }],
+            diff(&next, &snapshot())
+                .into_iter()
+                .filter(|c| matches!(c, DataChange::BusAdded { .. }))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rides() {
+        let previous = snapshot();
+        let mut next = previous.clone();
+        let removed = next.schedule.pop().unwrap();
+        let retimed = &mut next.schedule[0];
+        retimed.departure += TimeDelta::minutes(10);
+        let retimed = retimed.clone();
+
+        let changes = diff(&previous, &next);
+
+        assert_eq!(2, changes.len());
+        assert!(changes.contains(&DataChange::RideRetimed {
+            position: retimed.position.clone(),
+            start: retimed.start,
+            departure: (
+                retimed.departure - TimeDelta::minutes(10),
+                retimed.departure
+            ),
+            arrival: (retimed.arrival, retimed.arrival),
+        }));
+        assert!(changes.contains(&DataChange::RideRemoved {
+            position: removed.position.clone(),
+            start: removed.start,
+            departure: removed.departure,
+            arrival: removed.arrival,
+        }));
+    }
+
+    #[test]
+    fn stops() {
+        let previous = snapshot();
+        let mut next = previous.clone();
+        next.stops[0].name = "Renamed".to_string();
+        next.stops[1].coordinates = Coordinates::new(
+            Longitude(next.stops[1].coordinates.longitude.0 + 0.001),
+            Latitude(next.stops[1].coordinates.latitude.0),
+        );
+        let added = next.stops.remove(2);
+
+        let changes = diff(&next, &previous);
+
+        assert_eq!(3, changes.len());
+        assert!(matches!(
+            &changes[0],
+            DataChange::StopMoved { name, distance, .. }
+                if *name == previous.stops[1].name && *distance > 100.0
+        ));
+        assert_eq!(
+            vec![
+                DataChange::StopRenamed {
+                    direction: previous.stops[0].route_direction,
+                    from: "Renamed".to_string(),
+                    to: previous.stops[0].name.clone(),
+                },
+                DataChange::StopAdded {
+                    direction: added.route_direction,
+                    name: added.name.clone(),
+                    coordinates: added.coordinates,
+                },
+            ],
+            changes[1..]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::config::{Config, DataSourceConfig};
+
+/// A table of the spreadsheet, each stored as the JSON returned by the Sheets API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Dataset {
+    Buses,
+    Schedule,
+    Stops,
+}
+
+impl Dataset {
+    pub const ALL: [Self; 3] = [Self::Buses, Self::Schedule, Self::Stops];
+
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::Buses => "buses.json",
+            Self::Schedule => "schedule.json",
+            Self::Stops => "stops.json",
+        }
+    }
+}
+
+/// Where [`crate::services::FetchService`] reads buses, schedule and stops from.
+pub trait DataSource: Send + Sync {
+    fn open(&self, dataset: Dataset) -> anyhow::Result<Box<dyn Read + '_>>;
+}
+
+pub fn from_config(config: &Config) -> Box<dyn DataSource> {
+    match &config.data_source {
+        DataSourceConfig::Sheets => Box::new(SheetsSource {
+            buses: config.buses_url.clone(),
+            schedule: config.schedule_url.clone(),
+            stops: config.stops_url.clone(),
+        }),
+        DataSourceConfig::Directory(dir) => Box::new(DirectorySource { dir: dir.clone() }),
+        DataSourceConfig::Embedded => Box::new(MemorySource::embedded()),
+    }
+}
+
+/// The live spreadsheet over the Google Sheets API.
+/// Fields are the endpoints of the tables.
+pub struct SheetsSource {
+    pub buses: String,
+    pub schedule: String,
+    pub stops: String,
+}
+
+impl DataSource for SheetsSource {
+    fn open(&self, dataset: Dataset) -> anyhow::Result<Box<dyn Read + '_>> {
+        let url = match dataset {
+            Dataset::Buses => &self.buses,
+            Dataset::Schedule => &self.schedule,
+            Dataset::Stops => &self.stops,
+        };
+        Ok(Box::new(ureq::get(url).call()?.into_reader()))
+    }
+}
+
+/// A directory with `buses.json`, `schedule.json` and `stops.json`, as written by `fetch`.
+pub struct DirectorySource {
+    pub dir: PathBuf,
+}
+
+impl DataSource for DirectorySource {
+    fn open(&self, dataset: Dataset) -> anyhow::Result<Box<dyn Read + '_>> {
+        let path = self.dir.join(dataset.file_name());
+        let file = File::open(&path)
+            .map_err(|err| anyhow::anyhow!("Failed to open {}, {err}", path.display()))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Datasets kept in memory, e.g. for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    pub buses: Vec<u8>,
+    pub schedule: Vec<u8>,
+    pub stops: Vec<u8>,
+}
+
+impl DataSource for MemorySource {
+    fn open(&self, dataset: Dataset) -> anyhow::Result<Box<dyn Read + '_>> {
+        let data = match dataset {
+            Dataset::Buses => &self.buses,
+            Dataset::Schedule => &self.schedule,
+            Dataset::Stops => &self.stops,
+        };
+        Ok(Box::new(data.as_slice()))
+    }
+}
+
+impl MemorySource {
+    /// The snapshot of `data/` built into the binary.
+    pub fn embedded() -> Self {
+        Self {
+            buses: include_bytes!("../data/buses.json").to_vec(),
+            schedule: include_bytes!("../data/schedule.json").to_vec(),
+            stops: include_bytes!("../data/stops.json").to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::domain::{parse_list, Bus, Schedule, Stop};
+
+    use super::*;
+
+    fn assert_test_data(source: &dyn DataSource) {
+        assert_eq!(
+            14,
+            parse_list::<_, Bus>(source.open(Dataset::Buses).unwrap())
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            34,
+            parse_list::<_, Schedule>(source.open(Dataset::Schedule).unwrap())
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            52,
+            parse_list::<_, Stop>(source.open(Dataset::Stops).unwrap())
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn directory() {
+        assert_test_data(&DirectorySource {
+            dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data"),
+        });
+    }
+
+    #[test]
+    fn memory() {
+        assert_test_data(&MemorySource::embedded());
+    }
+
+    #[rstest]
+    #[case(Dataset::Buses)]
+    #[case(Dataset::Stops)]
+    fn missing_file(#[case] dataset: Dataset) {
+        let source = DirectorySource {
+            dir: PathBuf::from("missing"),
+        };
+
+        let Err(err) = source.open(dataset) else {
+            panic!("Opened a missing file");
+        };
+        assert!(err.to_string().contains(dataset.file_name()));
+    }
+}
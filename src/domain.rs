@@ -1,13 +1,17 @@
 use std::io::Read;
 
-use serde::Deserialize;
+use chrono::{FixedOffset, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 mod buses;
 mod coordinates;
 mod location;
+mod network;
 mod ride;
+mod route;
 mod route_direction;
+mod row;
 mod schedule;
 mod stops;
 mod terminal;
@@ -16,8 +20,11 @@ pub use buses::Bus;
 #[allow(unused_imports)]
 pub use coordinates::{Coordinates, Latitude, Longitude};
 pub use location::Location;
+pub use network::{Line, Network};
 pub use ride::Ride;
+pub use route::{Route, RoutePosition};
 pub use route_direction::RouteDirection;
+pub use row::{Aliases, CellError, Column, FromRow, Header, Row};
 pub use schedule::Schedule;
 pub use stops::Stop;
 pub use terminal::Terminal;
@@ -55,10 +62,37 @@ macro_rules! test_parse {
     };
 }
 
-pub fn parse_list<R: Read, T>(input: R) -> anyhow::Result<Vec<T>>
-where
-    T: for<'a> TryFrom<&'a Value, Error = anyhow::Error>,
-{
+/// The current local Phuket time, UTC+7 without daylight saving, as the sheet and GPS times
+/// are, whatever the time zone of the host.
+pub fn phuket_now() -> NaiveDateTime {
+    Utc::now()
+        .with_timezone(&FixedOffset::east_opt(7 * 60 * 60).expect("Valid offset"))
+        .naive_local()
+}
+
+/// A row of a sheet that failed to parse and has been left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejection {
+    /// Row number as shown in the sheet, the header being row 1.
+    pub row: usize,
+    /// The column of the failed cell, if a single cell is to blame.
+    pub column: Option<String>,
+    /// The failed cell, or the whole row.
+    pub value: String,
+    pub reason: String,
+}
+
+#[cfg(test)]
+pub fn parse_list<R: Read, T: FromRow>(input: R) -> anyhow::Result<Vec<T>> {
+    parse_rows(input, &Aliases::new()).map(|(list, _)| list)
+}
+
+/// Parses the rows of a sheet by the columns named in its header row,
+/// returning the rejected rows next to the parsed.
+pub fn parse_rows<R: Read, T: FromRow>(
+    input: R,
+    aliases: &Aliases,
+) -> anyhow::Result<(Vec<T>, Vec<Rejection>)> {
     #[derive(Debug, Deserialize)]
     struct Input {
         #[serde(rename = "range")]
@@ -70,18 +104,28 @@ where
         values: Vec<Value>,
     }
 
-    Ok(serde_json::from_reader::<_, Input>(input)?
-        .values
-        .iter()
-        .skip(1) // Skip "header" row
-        .map(TryInto::try_into)
-        .filter_map(Result::ok)
-        .collect())
-}
+    let values = serde_json::from_reader::<_, Input>(input)?.values;
+    let Some((header, rows)) = values.split_first() else {
+        anyhow::bail!("missing header row");
+    };
+    let header = Header::new(header, T::COLUMNS, aliases)?;
 
-pub fn fetch<T>(endpoint: &str) -> anyhow::Result<Vec<T>>
-where
-    T: for<'a> TryFrom<&'a Value, Error = anyhow::Error>,
-{
-    parse_list(ureq::get(endpoint).call()?.into_reader())
+    let mut list = vec![];
+    let mut rejections = vec![];
+    for (index, value) in rows.iter().enumerate() {
+        match Row::new(&header, value).and_then(|row| T::from_row(&row)) {
+            Ok(item) => list.push(item),
+            Err(err) => {
+                let cell = err.downcast_ref::<CellError>();
+                rejections.push(Rejection {
+                    // After the header row, numbered from 1.
+                    row: index + 2,
+                    column: cell.map(|c| c.column.clone()),
+                    value: cell.map_or_else(|| value.to_string(), |c| c.value.clone()),
+                    reason: cell.map_or_else(|| format!("{err:#}"), |c| c.reason.clone()),
+                });
+            }
+        }
+    }
+    Ok((list, rejections))
 }
@@ -1,8 +1,9 @@
 #![allow(dead_code)]
 
-use anyhow::{bail, ensure, Result};
+use anyhow::Result;
 use serde::Deserialize;
-use serde_json::Value;
+
+use super::{Column, FromRow, Row};
 
 #[derive(Debug, Clone)]
 pub struct Bus {
@@ -40,44 +41,25 @@ pub enum Direction {
     B,
 }
 
-impl TryFrom<&Value> for Bus {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &Value) -> Result<Self, Self::Error> {
-        let Some(array) = value.as_array() else {
-            bail!("expected array");
-        };
-
-        ensure!(array.len() == 17, "expected 17 items, got {}", array.len());
-
-        let get_str = |index: usize| {
-            let s = array[index]
-                .as_str()
-                .ok_or_else(|| anyhow::anyhow!("expected str at position {index}"))
-                .map(ToString::to_string);
-            s
-        };
+impl FromRow for Bus {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("no"),
+        Column::new("licence_plate_no"),
+        Column::new("bus_id"),
+        Column::new("service_status"),
+        Column::new("direction"),
+        Column::new("bus operate position"),
+    ];
 
+    fn from_row(row: &Row) -> Result<Self> {
         Ok(Self {
-            no: get_str(0)?.parse()?,
-            licence_plate_no: get_str(1)?,
-            id: get_str(2)?,
-            // _icon: get_str(3)?,
-            service_status: serde_json::from_value(array[4].clone())?,
-            direction: serde_json::from_value(array[5].clone())?,
-            operate_position: get_str(6)?,
-            // _a: get_str(7)?,
-            // _b: get_str(8)?,
-            // _c: get_str(9)?,
-            // _d: get_str(10)?,
-            // _e: get_str(11)?,
-            // _f: get_str(12)?,
-            // _concat: get_str(13)?,
-            // _run: get_str(14)?,
-            // date_time: NaiveDateTime::new(
-            //     NaiveDate::parse_from_str(&get_str(15)?, "%d/%m/%Y")?,
-            //     NaiveTime::parse_from_str(&get_str(16)?, "%r")?,
-            // ),
+            no: row.parse("no")?,
+            licence_plate_no: row.string("licence_plate_no")?,
+            id: row.string("bus_id")?,
+            service_status: row
+                .cell("service_status", |v| Ok(serde_json::from_value(v.clone())?))?,
+            direction: row.cell("direction", |v| Ok(serde_json::from_value(v.clone())?))?,
+            operate_position: row.string("bus operate position")?,
         })
     }
 }
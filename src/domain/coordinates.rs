@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use serde::Deserialize;
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
 
@@ -95,6 +95,15 @@ impl Coordinates {
     }
 }
 
+impl Serialize for Coordinates {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Coordinates", 2)?;
+        state.serialize_field("lat", &self.latitude.0)?;
+        state.serialize_field("lng", &self.longitude.0)?;
+        state.end()
+    }
+}
+
 impl Display for Coordinates {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
@@ -141,6 +150,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ser() {
+        assert_eq!(
+            r#"{"lat":7.882165,"lng":98.359085}"#,
+            serde_json::to_string(&Coordinates::new(
+                Longitude(98.359_085),
+                Latitude(7.882_165)
+            ))
+            .unwrap()
+        );
+    }
+
     #[test]
     #[allow(clippy::float_cmp)]
     fn test_harvesine_distance() {
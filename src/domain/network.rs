@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use super::{Route, RouteDirection, Stop, Terminal};
+
+/// A named line between two terminals, e.g. the Patong line from the airport.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Line {
+    /// E.g. `airport-patong`.
+    pub id: String,
+    /// E.g. `Airport - Patong`.
+    pub name: String,
+    pub terminals: (Terminal, Terminal),
+}
+
+impl Line {
+    fn new(a: Terminal, b: Terminal) -> Self {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        Self {
+            id: format!("{a}-{b}").to_lowercase(),
+            name: format!("{a} - {b}"),
+            terminals: (a, b),
+        }
+    }
+}
+
+/// The stops served by the rides of a line from one terminal to the other.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub line: Line,
+    pub direction: RouteDirection,
+    pub route: Route,
+    /// Distance along the route of the direction to the first stop of the pattern, meters.
+    pub offset: f64,
+}
+
+/// The routes of the network, from the stop patterns of the sheet and the terminals
+/// the schedule runs between.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    /// Patterns by the start and the destination terminal of the rides.
+    patterns: HashMap<(Terminal, Terminal), Pattern>,
+}
+
+impl Network {
+    pub fn build(stops: &[Stop], rides: impl IntoIterator<Item = (Terminal, Terminal)>) -> Self {
+        // Every stop of a direction in travel order, the destination terminal included.
+        let directions = stops
+            .iter()
+            .map(|s| s.route_direction)
+            .unique()
+            .map(|terminal| {
+                let route = stops
+                    .iter()
+                    .filter(|s| s.route_direction == terminal)
+                    .sorted_by_key(|s| s.order)
+                    .chain(terminal_stop(stops, terminal))
+                    .cloned()
+                    .collect();
+                (RouteDirection(terminal), Route::new(route))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let patterns = rides
+            .into_iter()
+            .unique()
+            .filter_map(|(start, stop)| {
+                let pattern = find_pattern(&directions, stops, start, stop)?;
+                Some(((start, stop), pattern))
+            })
+            .collect();
+
+        Self { patterns }
+    }
+
+    pub fn pattern(&self, start: Terminal, stop: Terminal) -> Option<&Pattern> {
+        self.patterns.get(&(start, stop))
+    }
+
+    /// Lines with any pattern, ordered by their terminals.
+    pub fn lines(&self) -> Vec<Line> {
+        self.patterns
+            .values()
+            .map(|p| p.line.clone())
+            .unique_by(|l| l.terminals)
+            .sorted_by_key(|l| l.terminals)
+            .collect()
+    }
+}
+
+fn terminal_stop(stops: &[Stop], terminal: Terminal) -> Option<&Stop> {
+    stops.iter().find(|s| s.name == terminal.stop_name())
+}
+
+/// Cuts the rides from `start` to `stop` out of the direction which passes both in this order,
+/// snapping the terminals to the closest stops.
+fn find_pattern(
+    directions: &HashMap<RouteDirection, Route>,
+    stops: &[Stop],
+    start: Terminal,
+    stop: Terminal,
+) -> Option<Pattern> {
+    let from = terminal_stop(stops, start)?.coordinates;
+    let to = terminal_stop(stops, stop)?.coordinates;
+
+    let nearest = |route: &Route, coordinates: super::Coordinates| {
+        route
+            .stops()
+            .iter()
+            .map(|s| s.coordinates.distance_to(coordinates))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    };
+
+    let (direction, (first, _), (last, _)) = directions
+        .iter()
+        .filter_map(|(direction, route)| {
+            let first = nearest(route, from)?;
+            let last = nearest(route, to)?;
+            (first.0 < last.0).then_some((*direction, first, last))
+        })
+        .min_by(|a, b| (a.1 .1 + a.2 .1).total_cmp(&(b.1 .1 + b.2 .1)))?;
+
+    let stops = directions[&direction].stops();
+    Some(Pattern {
+        line: Line::new(start, stop),
+        direction,
+        route: Route::new(stops[first..=last].to_vec()),
+        offset: stops[..=first]
+            .windows(2)
+            .map(|w| w[0].coordinates.distance_to(w[1].coordinates))
+            .sum(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::domain::{parse_list, Schedule, TEST_SCHEDULE, TEST_STOPS};
+
+    use super::*;
+
+    fn network() -> Network {
+        let stops = parse_list::<_, Stop>(TEST_STOPS).unwrap();
+        let schedule = parse_list::<_, Schedule>(TEST_SCHEDULE).unwrap();
+        Network::build(&stops, schedule.iter().map(|s| (s.start, s.destination)))
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            vec![
+                "airport-rawai",
+                "airport-kata",
+                "airport-patong",
+                "rawai-patong"
+            ],
+            network()
+                .lines()
+                .iter()
+                .map(|l| l.id.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[rstest]
+    #[case(
+        Terminal::Airport,
+        Terminal::Rawai,
+        Terminal::Rawai,
+        "Phuket Airport",
+        "Rawai Beach"
+    )]
+    #[case(
+        Terminal::Rawai,
+        Terminal::Airport,
+        Terminal::Airport,
+        "Rawai Beach",
+        "Phuket Airport"
+    )]
+    #[case(
+        Terminal::Kata,
+        Terminal::Airport,
+        Terminal::Airport,
+        "Kata Palm",
+        "Phuket Airport"
+    )]
+    #[case(
+        Terminal::Airport,
+        Terminal::Patong,
+        Terminal::Rawai,
+        "Phuket Airport",
+        "Patong PEA"
+    )]
+    #[case(
+        Terminal::Patong,
+        Terminal::Rawai,
+        Terminal::Rawai,
+        "Patong PEA",
+        "Rawai Beach"
+    )]
+    #[case(
+        Terminal::Rawai,
+        Terminal::Patong,
+        Terminal::Airport,
+        "Rawai Beach",
+        "Bangla Patong"
+    )]
+    fn pattern(
+        #[case] start: Terminal,
+        #[case] stop: Terminal,
+        #[case] direction: Terminal,
+        #[case] first: &str,
+        #[case] last: &str,
+    ) {
+        let network = network();
+        let pattern = network.pattern(start, stop).unwrap();
+
+        assert_eq!(RouteDirection(direction), pattern.direction);
+        assert_eq!(first, pattern.route.stops().first().unwrap().name);
+        assert_eq!(last, pattern.route.stops().last().unwrap().name);
+    }
+
+    #[test]
+    fn between_intermediate_terminals() {
+        let stops = parse_list::<_, Stop>(TEST_STOPS).unwrap();
+        let network = Network::build(
+            &stops,
+            [
+                (Terminal::Kata, Terminal::Patong),
+                (Terminal::Patong, Terminal::Kata),
+            ],
+        );
+
+        assert_eq!(
+            RouteDirection(Terminal::Airport),
+            network
+                .pattern(Terminal::Kata, Terminal::Patong)
+                .unwrap()
+                .direction
+        );
+        assert_eq!(
+            RouteDirection(Terminal::Rawai),
+            network
+                .pattern(Terminal::Patong, Terminal::Kata)
+                .unwrap()
+                .direction
+        );
+        assert!(
+            network
+                .pattern(Terminal::Kata, Terminal::Patong)
+                .unwrap()
+                .offset
+                > 0.0
+        );
+        assert_eq!("kata-patong", network.lines()[0].id);
+    }
+}
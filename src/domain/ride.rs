@@ -1,10 +1,11 @@
 use std::{cmp::Ordering, fmt::Display};
 
 use chrono::NaiveTime;
+use serde::Serialize;
 
-use super::{route_direction::RouteDirection, schedule::Schedule, Terminal};
+use super::{schedule::Schedule, Terminal};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Ride {
     pub name: String,
     pub start: Terminal,
@@ -28,12 +29,6 @@ impl PartialOrd for Ride {
     }
 }
 
-impl Ride {
-    pub fn direction(&self) -> RouteDirection {
-        RouteDirection::from((self.start, self.stop))
-    }
-}
-
 impl From<Schedule> for Ride {
     fn from(s: Schedule) -> Self {
         Self {
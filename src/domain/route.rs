@@ -0,0 +1,181 @@
+use super::{Coordinates, Stop};
+
+/// Mean Earth radius used by the local projection, meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A route polyline through the stops of one direction, in travel order.
+#[derive(Debug, Clone)]
+pub struct Route {
+    stops: Vec<Stop>,
+    /// Distance along the route to every stop, meters.
+    distances: Vec<f64>,
+}
+
+/// A location projected onto a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePosition {
+    pub previous: Stop,
+    pub next: Stop,
+    /// Distance travelled along the route from its first stop, meters.
+    pub distance: f64,
+    /// Travelled fraction of the segment between the previous and the next stop, `0..=1`.
+    pub fraction: f64,
+    /// Distance between the location and the route, meters.
+    pub offset: f64,
+}
+
+impl Route {
+    pub fn new(stops: Vec<Stop>) -> Self {
+        let mut distances = Vec::with_capacity(stops.len());
+        let mut distance = 0.0;
+        for (index, stop) in stops.iter().enumerate() {
+            if index > 0 {
+                distance += stops[index - 1].coordinates.distance_to(stop.coordinates);
+            }
+            distances.push(distance);
+        }
+
+        Self { stops, distances }
+    }
+
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    /// Projects the location onto the closest segment of the route.
+    pub fn project(&self, pos: Coordinates) -> Option<RoutePosition> {
+        let (index, fraction, offset) = self
+            .stops
+            .windows(2)
+            .enumerate()
+            .map(|(index, segment)| {
+                let (fraction, offset) =
+                    project_on_segment(pos, segment[0].coordinates, segment[1].coordinates);
+                (index, fraction, offset)
+            })
+            // The first of equally close segments wins, so a bus at a stop has arrived to it.
+            .min_by(|a, b| a.2.total_cmp(&b.2))?;
+
+        let segment = self.distances[index + 1] - self.distances[index];
+        Some(RoutePosition {
+            previous: self.stops[index].clone(),
+            next: self.stops[index + 1].clone(),
+            distance: fraction.mul_add(segment, self.distances[index]),
+            fraction,
+            offset,
+        })
+    }
+}
+
+/// Returns the travelled fraction of the `a -> b` segment at the closest point to `pos`,
+/// and the distance to that point, meters.
+fn project_on_segment(pos: Coordinates, a: Coordinates, b: Coordinates) -> (f64, f64) {
+    // Equirectangular projection around `a` is precise enough for segments of a few kilometers.
+    let scale = f64::from(a.latitude.0).to_radians().cos();
+    let xy = |c: Coordinates| {
+        (
+            f64::from(c.longitude.0 - a.longitude.0).to_radians() * scale * EARTH_RADIUS,
+            f64::from(c.latitude.0 - a.latitude.0).to_radians() * EARTH_RADIUS,
+        )
+    };
+
+    let (px, py) = xy(pos);
+    let (bx, by) = xy(b);
+
+    let length = bx.mul_add(bx, by * by);
+    let fraction = if length > 0.0 {
+        (px.mul_add(bx, py * by) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (dx, dy) = (fraction.mul_add(bx, -px), fraction.mul_add(by, -py));
+    (fraction, dx.hypot(dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{Latitude, Longitude, Terminal};
+
+    use super::*;
+
+    fn stop(name: &str, longitude: f32, latitude: f32) -> Stop {
+        Stop {
+            order: 0,
+            name_th: String::new(),
+            name: name.to_string(),
+            description: None,
+            route_direction: Terminal::Airport,
+            coordinates: Coordinates::new(Longitude(longitude), Latitude(latitude)),
+            schedule: vec![],
+            icon: String::new(),
+            color: String::new(),
+            unique_id: None,
+            image: String::new(),
+            map_link: String::new(),
+            display: true,
+        }
+    }
+
+    /// A route which goes north and then doubles back south, like a hill road.
+    fn route() -> Route {
+        Route::new(vec![
+            stop("A", 98.30, 7.80),
+            stop("B", 98.30, 7.81),
+            stop("C", 98.31, 7.81),
+            stop("D", 98.31, 7.80),
+        ])
+    }
+
+    #[test]
+    fn project_on_doubled_back_segment() {
+        let route = route();
+
+        // South of C, on the way back, latitude order would put it between A and B.
+        let position = route
+            .project(Coordinates::new(Longitude(98.3099), Latitude(7.805)))
+            .unwrap();
+
+        assert_eq!("C", position.previous.name);
+        assert_eq!("D", position.next.name);
+        assert!((position.fraction - 0.5).abs() < 0.01);
+        assert!(position.offset < 15.0);
+        assert!(position.distance > route.distances[2]);
+    }
+
+    #[test]
+    fn project_on_stop() {
+        let route = route();
+
+        let position = route
+            .project(Coordinates::new(Longitude(98.30), Latitude(7.81)))
+            .unwrap();
+
+        assert_eq!("A", position.previous.name);
+        assert_eq!("B", position.next.name);
+        assert!((position.fraction - 1.0).abs() < f64::EPSILON);
+        assert!(position.offset < 1.0);
+        assert!((position.distance - route.distances[1]).abs() < 1.0);
+    }
+
+    #[test]
+    fn offset_from_route() {
+        let position = route()
+            .project(Coordinates::new(Longitude(98.29), Latitude(7.805)))
+            .unwrap();
+
+        assert_eq!("A", position.previous.name);
+        // 0.01° of longitude at this latitude is about 1.1km.
+        assert!((position.offset - 1_102.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn length() {
+        let route = route();
+
+        assert!((route.distances[3] - 3_320.0).abs() < 20.0);
+        assert!(Route::new(vec![])
+            .project(Coordinates::new(Longitude(98.29), Latitude(7.805)))
+            .is_none());
+    }
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+
+/// Alternative header names by the column they stand for, e.g. `departue time` for
+/// `departure time`.
+pub type Aliases = HashMap<String, String>;
+
+/// Missing trailing cells, the Sheets API leaves empty ones out.
+static EMPTY: Value = Value::String(String::new());
+
+/// A column read from a sheet, found by its name or one of the aliases in the header row.
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Whether the header may lack the column, its cells then read as empty.
+    pub optional: bool,
+}
+
+impl Column {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            aliases: &[],
+            optional: false,
+        }
+    }
+
+    pub const fn with_aliases(name: &'static str, aliases: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            aliases,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str) -> Self {
+        Self {
+            name,
+            aliases: &[],
+            optional: true,
+        }
+    }
+}
+
+/// A type parsed from the rows of a sheet.
+pub trait FromRow: Sized {
+    /// The columns read by [`FromRow::from_row`], required in the header unless optional.
+    const COLUMNS: &'static [Column];
+
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Positions of the columns in the header row, `None` for missing optional ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header(HashMap<&'static str, Option<usize>>);
+
+impl Header {
+    pub fn new(row: &Value, columns: &[Column], aliases: &Aliases) -> Result<Self> {
+        let Some(names) = row.as_array() else {
+            bail!("expected header array");
+        };
+        let names = names
+            .iter()
+            .map(|name| name.as_str().map(normalize).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let mut positions = HashMap::new();
+        for column in columns {
+            let configured = aliases
+                .iter()
+                .filter(|(_, name)| normalize(name) == column.name)
+                .map(|(alias, _)| normalize(alias));
+            let candidates = std::iter::once(&column.name)
+                .chain(column.aliases)
+                .map(|name| normalize(name))
+                .chain(configured)
+                .collect::<Vec<_>>();
+            let position = names.iter().position(|name| candidates.contains(name));
+            if position.is_none() && !column.optional {
+                bail!("missing column {:?} in the header", column.name);
+            }
+            positions.insert(column.name, position);
+        }
+        Ok(Self(positions))
+    }
+}
+
+/// A cell of a sheet row that failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellError {
+    pub column: String,
+    /// The cell as it is in the sheet.
+    pub value: String,
+    pub reason: String,
+}
+
+impl Display for CellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at column {:?}, got {:?}",
+            self.reason, self.column, self.value
+        )
+    }
+}
+
+impl std::error::Error for CellError {}
+
+/// A row of a sheet, reporting failures with the cell they come from.
+pub struct Row<'a> {
+    header: &'a Header,
+    cells: &'a [Value],
+}
+
+impl<'a> Row<'a> {
+    pub fn new(header: &'a Header, value: &'a Value) -> Result<Self> {
+        let Some(cells) = value.as_array() else {
+            bail!("expected array");
+        };
+        Ok(Self { header, cells })
+    }
+
+    /// Reads the cell of the column with `f`, failing with a [`CellError`].
+    pub fn cell<T>(&self, column: &str, f: impl FnOnce(&'a Value) -> Result<T>) -> Result<T> {
+        let Some(&position) = self.header.0.get(column) else {
+            bail!("unknown column {column:?}");
+        };
+        let value = position
+            .and_then(|position| self.cells.get(position))
+            .unwrap_or(&EMPTY);
+        f(value).map_err(|err| {
+            anyhow!(CellError {
+                column: column.to_string(),
+                value: value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), ToString::to_string),
+                reason: format!("{err:#}"),
+            })
+        })
+    }
+
+    pub fn str(&self, column: &str) -> Result<&'a str> {
+        self.cell(column, |value| {
+            value.as_str().ok_or_else(|| anyhow!("expected str"))
+        })
+    }
+
+    pub fn string(&self, column: &str) -> Result<String> {
+        self.str(column).map(ToString::to_string)
+    }
+
+    pub fn parse<T>(&self, column: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Into<anyhow::Error>,
+    {
+        self.cell(column, |value| {
+            value
+                .as_str()
+                .ok_or_else(|| anyhow!("expected str"))?
+                .parse()
+                .map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const COLUMNS: &[Column] = &[
+        Column::new("no"),
+        Column::with_aliases("name", &["title"]),
+        Column::new("note"),
+        Column::optional("extra"),
+    ];
+
+    #[test]
+    fn header() {
+        let header = Header::new(
+            &json!(["Title", "", " NO ", "remark"]),
+            COLUMNS,
+            &Aliases::from([("Remark".to_string(), "note".to_string())]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Header(HashMap::from([
+                ("no", Some(2)),
+                ("name", Some(0)),
+                ("note", Some(3)),
+                ("extra", None),
+            ])),
+            header
+        );
+        let Err(err) = Header::new(&json!(["no", "name"]), COLUMNS, &Aliases::new()) else {
+            panic!("Missing column accepted");
+        };
+        assert!(err.to_string().contains("\"note\""));
+    }
+
+    #[test]
+    fn cell_error() {
+        let header = Header::new(&json!(["no", "name", "note"]), COLUMNS, &Aliases::new()).unwrap();
+        let value = json!(["1", 2]);
+        let row = Row::new(&header, &value).unwrap();
+
+        assert_eq!(1, row.parse::<u8>("no").unwrap());
+        assert_eq!("", row.str("note").unwrap());
+        assert_eq!("", row.str("extra").unwrap());
+        assert!(row.str("other").is_err());
+        let err = row.str("name").unwrap_err();
+        let cell = err.downcast_ref::<CellError>().unwrap();
+        assert_eq!("name", cell.column);
+        assert_eq!("2", cell.value);
+        assert_eq!("expected str", cell.reason);
+        assert!(row
+            .parse::<u8>("note")
+            .unwrap_err()
+            .downcast_ref::<CellError>()
+            .is_some());
+    }
+}
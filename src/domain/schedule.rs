@@ -2,12 +2,11 @@
 
 use std::str::FromStr;
 
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, Result};
 use chrono::NaiveTime;
 use serde::Deserialize;
-use serde_json::Value;
 
-use super::Terminal;
+use super::{Column, FromRow, Row, Terminal};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Schedule {
@@ -36,32 +35,28 @@ impl FromStr for Direction {
     }
 }
 
-impl TryFrom<&Value> for Schedule {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &Value) -> Result<Self, Self::Error> {
-        let Some(array) = value.as_array() else {
-            bail!("expected array");
-        };
-
-        ensure!(array.len() == 8, "expected 8 items, got {}", array.len());
-
-        let get_str = |index: usize| {
-            array[index]
-                .as_str()
-                .ok_or_else(|| anyhow::anyhow!("expected str at position {index}"))
-                .map(ToString::to_string)
-        };
-
+impl FromRow for Schedule {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("bus operate position"),
+        Column::new("start"),
+        Column::with_aliases("departure time", &["departue time"]),
+        Column::new("color changed"),
+        Column::with_aliases("arrival time", &["arroval time"]),
+        Column::new("destination"),
+        Column::new("direction"),
+        Column::new("icon"),
+    ];
+
+    fn from_row(row: &Row) -> Result<Self> {
         Ok(Self {
-            position: get_str(0)?,
-            start: get_str(1)?.parse()?,
-            departure: *SmartBusTime::from_str(&get_str(2)?)?.as_ref(),
-            color_changed: *SmartBusTime::from_str(&get_str(3)?)?.as_ref(),
-            arrival: *SmartBusTime::from_str(&get_str(4)?)?.as_ref(),
-            destination: get_str(5)?.parse()?,
-            direction: Direction::from_str(&get_str(6)?)?.0,
-            icon: get_str(7)?,
+            position: row.string("bus operate position")?,
+            start: row.parse("start")?,
+            departure: row.parse::<SmartBusTime>("departure time")?.0,
+            color_changed: row.parse::<SmartBusTime>("color changed")?.0,
+            arrival: row.parse::<SmartBusTime>("arrival time")?.0,
+            destination: row.parse("destination")?,
+            direction: row.parse::<Direction>("direction")?.0,
+            icon: row.string("icon")?,
         })
     }
 }
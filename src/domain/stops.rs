@@ -2,13 +2,13 @@
 
 use std::str::FromStr;
 
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, bail, Result};
 use chrono::NaiveTime;
-use serde_json::Value;
+use serde::Serialize;
 
-use super::{Coordinates, Terminal};
+use super::{Column, Coordinates, FromRow, Row, Terminal};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Stop {
     pub order: usize,
     pub name_th: String,
@@ -33,9 +33,10 @@ impl FromStr for BusDisplay {
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
-            "on" => Ok(Self(true)),
+            // Shown unless switched off, also without the column.
+            "on" | "" => Ok(Self(true)),
             "off" => Ok(Self(false)),
-            _ => bail!("unknown bus display: {}", s),
+            _ => bail!("unknown bus display: {s}"),
         }
     }
 }
@@ -85,50 +86,79 @@ impl FromStr for StopDescription {
     }
 }
 
-impl TryFrom<&Value> for Stop {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &Value) -> Result<Self, Self::Error> {
-        let Some(array) = value.as_array() else {
-            bail!("expected array");
-        };
-
-        ensure!(array.len() == 14, "expected 14 items, got {}", array.len());
-
-        let get_str = |index: usize| {
-            array[index]
-                .as_str()
-                .ok_or_else(|| anyhow::anyhow!("expected str at position {index}"))
-                .map(ToString::to_string)
-        };
-
+impl FromRow for Stop {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("order"),
+        Column::new("stop_name_th"),
+        Column::new("stop_name_eng"),
+        Column::new("description"),
+        Column::new("route_direction"),
+        Column::new("lng"),
+        Column::new("lat"),
+        Column::new("times"),
+        Column::optional("icon"),
+        Column::optional("color"),
+        Column::new("bus stop unique id"),
+        Column::optional("image bus stop"),
+        Column::optional("direction link"),
+        Column::optional("display"),
+    ];
+
+    fn from_row(row: &Row) -> Result<Self> {
         Ok(Self {
-            order: get_str(0)?.parse()?,
-            name_th: get_str(1)?.trim().to_string(),
-            name: get_str(2)?.trim().to_string(),
-            description: StopDescription::from_str(&get_str(3)?)?.0,
-            route_direction: RouteDirection::from_str(&get_str(4)?)?.0,
+            order: row.parse("order")?,
+            name_th: row.str("stop_name_th")?.trim().to_string(),
+            name: row.str("stop_name_eng")?.trim().to_string(),
+            description: row.parse::<StopDescription>("description")?.0,
+            route_direction: row.parse::<RouteDirection>("route_direction")?.0,
             coordinates: Coordinates::new(
-                // JSON messes lat/lng.
-                get_str(6)?.parse::<f32>()?.into(),
-                get_str(5)?.parse::<f32>()?.into(),
+                // The sheet messes lat/lng.
+                row.parse::<f32>("lat")?.into(),
+                row.parse::<f32>("lng")?.into(),
             ),
-            schedule: Schedule::from_str(&get_str(7)?)?.0,
-            icon: get_str(8)?,
-            color: get_str(9)?,
-            unique_id: get_str(10)?.parse().ok(),
-            image: get_str(11)?,
-            map_link: get_str(12)?,
-            display: BusDisplay::from_str(&get_str(13)?)?.0,
+            schedule: row.parse::<Schedule>("times")?.0,
+            icon: row.string("icon")?,
+            color: row.string("color")?,
+            unique_id: row.str("bus stop unique id")?.parse().ok(),
+            image: row.string("image bus stop")?,
+            map_link: row.string("direction link")?,
+            display: row.parse::<BusDisplay>("display")?.0,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{domain::TEST_STOPS, test_parse};
+    use serde_json::json;
+
+    use crate::{
+        domain::{parse_list, TEST_STOPS},
+        test_parse,
+    };
 
     use super::*;
 
     test_parse!(Stop, TEST_STOPS, 52);
+
+    #[test]
+    fn without_unused_columns() {
+        let sheet = json!({
+            "range": "BusStop!A1:AA100",
+            "majorDimension": "ROWS",
+            "values": [
+                ["order", "stop_name_th", "stop_name_eng", "description", "route_direction",
+                    "lng", "lat", "times", "Bus stop Unique ID"],
+                ["1", "หาดราไวย์", "Rawai Beach", "Rawai Bus Stop opposite the pier",
+                    "Rawai Beach --> Airport", "7.77", "98.32", "8:00AM", "7"],
+            ],
+        });
+
+        let stops = parse_list::<_, Stop>(sheet.to_string().as_bytes()).unwrap();
+
+        assert_eq!(1, stops.len());
+        assert_eq!("Rawai Beach", stops[0].name);
+        assert_eq!(Some(7), stops[0].unique_id);
+        assert_eq!("", stops[0].icon);
+        assert!(stops[0].display);
+    }
 }
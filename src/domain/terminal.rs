@@ -1,10 +1,9 @@
 use std::{fmt::Display, str::FromStr};
 
 use anyhow::bail;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use super::Stop;
-#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Terminal {
     Airport,
     Rawai,
@@ -21,7 +20,7 @@ impl FromStr for Terminal {
             "Rawai" => Ok(Self::Rawai),
             "Kata" => Ok(Self::Kata),
             "Patong" => Ok(Self::Patong),
-            _ => bail!("unknown terminal stop: {}", s),
+            _ => bail!("unknown terminal stop: {s}"),
         }
     }
 }
@@ -46,12 +45,4 @@ impl Terminal {
             Self::Patong => "Bangla Patong",
         }
     }
-
-    pub fn stop(self, stops: &[Stop]) -> Stop {
-        stops
-            .iter()
-            .find(|s| s.name == self.stop_name())
-            .unwrap()
-            .clone()
-    }
 }
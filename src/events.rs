@@ -0,0 +1,541 @@
+use std::{fmt::Display, io::Write, str::FromStr, sync::OnceLock};
+
+use anyhow::bail;
+use chrono::{NaiveDateTime, NaiveTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    data_diff::DataChange,
+    data_source::Dataset,
+    domain::{Coordinates, Rejection, RouteDirection, Terminal},
+};
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// How events are written, `text` for people, `json` for log pipelines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+    /// Nothing, e.g. while the terminal shows the arrival board.
+    Off,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "off" => Ok(Self::Off),
+            _ => bail!("unknown log format: {s}"),
+        }
+    }
+}
+
+/// Sets the format of all further events, the first call wins.
+pub fn init(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => f.write_str("INFO"),
+            Self::Warn => f.write_str("WARN"),
+            Self::Error => f.write_str("ERROR"),
+        }
+    }
+}
+
+/// Everything the service reports while running.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    Connected,
+    Disconnected,
+    SocketError {
+        error: String,
+    },
+    UnknownMessage {
+        name: String,
+        payload: String,
+    },
+    ParseFailed {
+        error: String,
+        payload: String,
+    },
+    LocationRejected {
+        car_license: String,
+        date_time: NaiveDateTime,
+        coordinates: Coordinates,
+        reason: String,
+    },
+    NonOperatingBus {
+        car_license: String,
+    },
+    PositionInferred {
+        car_license: String,
+        position: String,
+    },
+    NoRideForTime {
+        position: String,
+        car_license: String,
+        time: NaiveTime,
+    },
+    UnmatchedLocation {
+        ride: String,
+        start: Terminal,
+        stop: Terminal,
+        car_license: String,
+        coordinates: Coordinates,
+    },
+    PositionMatched {
+        date_time: NaiveDateTime,
+        car_license: String,
+        ride: String,
+        start: Terminal,
+        stop: Terminal,
+        previous_stop: String,
+        previous_stop_distance: f64,
+        next_stop: String,
+        next_stop_distance: f64,
+        eta: Option<NaiveDateTime>,
+        route_distance: f64,
+        speed: u32,
+        heading: f32,
+        altitude: u32,
+    },
+    OffRoute {
+        position: String,
+        car_license: String,
+        since: NaiveDateTime,
+        deviation: f64,
+    },
+    BackOnRoute {
+        position: String,
+        car_license: String,
+        deviation: f64,
+    },
+    /// The off-route bus is no longer tracked, e.g. its ride has ended.
+    OffRouteAbandoned {
+        position: String,
+        car_license: String,
+    },
+    Bunching {
+        direction: RouteDirection,
+        car_license: String,
+        leader: String,
+        distance: f64,
+        estimated_headway: i64,
+        scheduled: Option<i64>,
+    },
+    HeadwayGap {
+        direction: RouteDirection,
+        car_license: String,
+        leader: String,
+        distance: f64,
+        estimated_headway: i64,
+        scheduled: Option<i64>,
+    },
+    HeadwayRestored {
+        direction: RouteDirection,
+        car_license: String,
+        leader: String,
+        estimated_headway: i64,
+    },
+    StopPassed {
+        car_license: String,
+        ride: String,
+        stop: String,
+        passed_at: NaiveDateTime,
+        scheduled_deviation: Option<i64>,
+        advertised_deviation: Option<i64>,
+    },
+    ApproachingStop {
+        car_license: String,
+        ride: String,
+        stop: String,
+        date_time: NaiveDateTime,
+        /// Meters.
+        distance: f64,
+    },
+    ArrivedAtStop {
+        car_license: String,
+        ride: String,
+        stop: String,
+        date_time: NaiveDateTime,
+    },
+    DepartedStop {
+        car_license: String,
+        ride: String,
+        stop: String,
+        date_time: NaiveDateTime,
+        arrived_at: NaiveDateTime,
+        /// Seconds.
+        dwell: i64,
+    },
+    PassedStopWithoutStopping {
+        car_license: String,
+        ride: String,
+        stop: String,
+        date_time: NaiveDateTime,
+    },
+    DataFetching,
+    FetchFailed {
+        error: String,
+        retry_in_sec: u64,
+    },
+    /// Data of `source` has been reloaded, e.g. the sheets or the routes built from them.
+    DataRefreshed {
+        source: String,
+        version: u64,
+    },
+    /// A change of the sheets found by the refresh to `version`.
+    DataChanged {
+        version: u64,
+        #[serde(flatten)]
+        change: DataChange,
+    },
+    /// A row of a sheet left out of the data as it failed to parse.
+    RowRejected {
+        dataset: Dataset,
+        #[serde(flatten)]
+        rejection: Rejection,
+    },
+    HistoryPurged {
+        deleted: usize,
+    },
+    RideSkipped {
+        ride: String,
+        reason: String,
+    },
+    ApiListening {
+        addr: String,
+    },
+    /// Any failure with nothing else to report but the error.
+    Failed {
+        action: String,
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: chrono::DateTime<Utc>,
+    level: Level,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    pub fn failed(action: &str, err: &anyhow::Error) -> Self {
+        Self::Failed {
+            action: action.to_string(),
+            error: format!("{err:#}"),
+        }
+    }
+
+    pub const fn level(&self) -> Level {
+        match self {
+            Self::SocketError { .. }
+            | Self::ParseFailed { .. }
+            | Self::FetchFailed { .. }
+            | Self::Failed { .. } => Level::Error,
+            Self::NonOperatingBus { .. }
+            | Self::LocationRejected { .. }
+            | Self::NoRideForTime { .. }
+            | Self::UnmatchedLocation { .. }
+            | Self::OffRoute { .. }
+            | Self::Bunching { .. }
+            | Self::HeadwayGap { .. }
+            | Self::RowRejected { .. }
+            | Self::RideSkipped { .. } => Level::Warn,
+            _ => Level::Info,
+        }
+    }
+
+    pub fn render(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => format!("{} {self}", self.level()),
+            LogFormat::Json => serde_json::to_string(&Record {
+                timestamp: Utc::now(),
+                level: self.level(),
+                event: self,
+            })
+            .expect("Serializable event"),
+            LogFormat::Off => String::new(),
+        }
+    }
+
+    /// Writes the event, information to stdout, warnings and errors to stderr.
+    pub fn emit(&self) {
+        let format = FORMAT.get().copied().unwrap_or_default();
+        if format == LogFormat::Off {
+            return;
+        }
+        let line = self.render(format);
+        // Nowhere to report failures to write the log.
+        let _ = if self.level() == Level::Info {
+            writeln!(std::io::stdout().lock(), "{line}")
+        } else {
+            writeln!(std::io::stderr().lock(), "{line}")
+        };
+    }
+}
+
+fn optional<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+impl Display for Event {
+    #[allow(clippy::too_many_lines)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connected => f.write_str("Connected"),
+            Self::Disconnected => f.write_str("Disconnected"),
+            Self::SocketError { error } => write!(f, "Socket error, {error}"),
+            Self::UnknownMessage { name, payload } => write!(f, "{name}: {payload}"),
+            Self::ParseFailed { error, payload } => write!(f, "Failed to parse, {error}\n{payload}"),
+            Self::LocationRejected {
+                car_license,
+                date_time,
+                coordinates,
+                reason,
+            } => write!(
+                f,
+                "Location rejected as {reason}, license={car_license}, time={date_time}, coordinates={coordinates}"
+            ),
+            Self::NonOperatingBus { car_license } => {
+                write!(f, "Non-operating bus, license={car_license}")
+            }
+            Self::PositionInferred {
+                car_license,
+                position,
+            } => write!(f, "Position {position} inferred, license={car_license}"),
+            Self::NoRideForTime {
+                position,
+                car_license,
+                time,
+            } => write!(
+                f,
+                "Non-operating bus, position={position}, license={car_license}, time={time}"
+            ),
+            Self::UnmatchedLocation {
+                ride,
+                start,
+                stop,
+                car_license,
+                coordinates,
+            } => write!(
+                f,
+                "{ride}\t{start} => {stop}, can't match location {coordinates}, license={car_license}"
+            ),
+            Self::PositionMatched {
+                date_time,
+                car_license: _,
+                ride,
+                start,
+                stop,
+                previous_stop,
+                previous_stop_distance,
+                next_stop,
+                next_stop_distance,
+                eta,
+                route_distance,
+                speed,
+                heading,
+                altitude,
+            } => write!(
+                f,
+                "{date_time}\t{ride}\t{start} => {stop}, {previous_stop_distance}m from {previous_stop} => {next_stop_distance}m to {next_stop} (eta {}), {route_distance:.0}m along, speed={speed}kmh, heading={heading}°, altitude={altitude}m",
+                optional(eta.map(|t| t.time()))
+            ),
+            Self::OffRoute {
+                position,
+                car_license,
+                since,
+                deviation,
+            } => write!(
+                f,
+                "Off route since {}, position={position}, license={car_license}, deviation={deviation:.0}m",
+                since.time()
+            ),
+            Self::BackOnRoute {
+                position,
+                car_license,
+                deviation,
+            } => write!(
+                f,
+                "Back on route, position={position}, license={car_license}, deviation={deviation:.0}m"
+            ),
+            Self::OffRouteAbandoned {
+                position,
+                car_license,
+            } => write!(
+                f,
+                "No longer tracked off route, position={position}, license={car_license}"
+            ),
+            Self::Bunching {
+                direction,
+                car_license,
+                leader,
+                distance,
+                estimated_headway,
+                scheduled,
+            } => write!(
+                f,
+                "Bunching {direction} behind {leader}, license={car_license}, estimated_headway={estimated_headway}s vs {}s scheduled, distance={distance:.0}m",
+                optional(*scheduled)
+            ),
+            Self::HeadwayGap {
+                direction,
+                car_license,
+                leader,
+                distance,
+                estimated_headway,
+                scheduled,
+            } => write!(
+                f,
+                "Gap {direction} behind {leader}, license={car_license}, estimated_headway={estimated_headway}s vs {}s scheduled, distance={distance:.0}m",
+                optional(*scheduled)
+            ),
+            Self::HeadwayRestored {
+                direction,
+                car_license,
+                leader,
+                estimated_headway,
+            } => write!(
+                f,
+                "Headway restored {direction} behind {leader}, license={car_license}, estimated_headway={estimated_headway}s"
+            ),
+            Self::StopPassed {
+                car_license: _,
+                ride,
+                stop,
+                passed_at,
+                scheduled_deviation,
+                advertised_deviation,
+            } => write!(
+                f,
+                "{passed_at}\t{ride}\tpassed {stop}, {}s vs ride schedule, {}s vs stop schedule",
+                optional(scheduled_deviation.map(|d| format!("{d:+}"))),
+                optional(advertised_deviation.map(|d| format!("{d:+}")))
+            ),
+            Self::DataFetching => f.write_str("Fetching new data"),
+            Self::FetchFailed {
+                error,
+                retry_in_sec,
+            } => write!(f, "Failed to fetch {error}, retry in {retry_in_sec}s"),
+            Self::DataRefreshed { source, version } => {
+                write!(f, "{source} updated, version {version}")
+            }
+            Self::DataChanged { version, change } => write!(f, "Data version {version}, {change}"),
+            Self::RowRejected { dataset, rejection } => write!(
+                f,
+                "{dataset:?} row {} rejected, column {}, {}, got {:?}",
+                rejection.row,
+                optional(rejection.column.as_deref()),
+                rejection.reason,
+                rejection.value
+            ),
+            Self::ApproachingStop {
+                car_license: _,
+                ride,
+                stop,
+                date_time,
+                distance,
+            } => write!(f, "{date_time}\t{ride}\tapproaching {stop}, {distance:.0}m"),
+            Self::ArrivedAtStop {
+                car_license: _,
+                ride,
+                stop,
+                date_time,
+            } => write!(f, "{date_time}\t{ride}\tarrived at {stop}"),
+            Self::DepartedStop {
+                car_license: _,
+                ride,
+                stop,
+                date_time,
+                arrived_at: _,
+                dwell,
+            } => write!(f, "{date_time}\t{ride}\tdeparted {stop} after {dwell}s"),
+            Self::PassedStopWithoutStopping {
+                car_license: _,
+                ride,
+                stop,
+                date_time,
+            } => write!(f, "{date_time}\t{ride}\tpassed {stop} without stopping"),
+            Self::HistoryPurged { deleted } => write!(f, "History purged, {deleted} locations"),
+            Self::RideSkipped { ride, reason } => write!(f, "{reason} for ride {ride}, skipped"),
+            Self::ApiListening { addr } => write!(f, "HTTP API listening on {addr}"),
+            Self::Failed { action, error } => write!(f, "Failed to {action}, {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn off_route() -> Event {
+        Event::OffRoute {
+            position: "Bus7".to_string(),
+            car_license: "10-1152".to_string(),
+            since: NaiveDateTime::parse_from_str("2024-03-20 15:10:05", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            deviation: 312.4,
+        }
+    }
+
+    #[test]
+    fn render_text() {
+        assert_eq!(
+            "WARN Off route since 15:10:05, position=Bus7, license=10-1152, deviation=312m",
+            off_route().render(LogFormat::Text)
+        );
+        assert_eq!(
+            "INFO Buses updated, version 2",
+            Event::DataRefreshed {
+                source: "Buses".to_string(),
+                version: 2
+            }
+            .render(LogFormat::Text)
+        );
+    }
+
+    #[test]
+    fn render_json() {
+        let line = off_route().render(LogFormat::Json);
+
+        assert!(!line.contains('\n'));
+        let json = serde_json::from_str::<Value>(&line).unwrap();
+        assert_eq!("WARN", json["level"]);
+        assert_eq!("OffRoute", json["event"]);
+        assert_eq!("10-1152", json["car_license"]);
+        assert_eq!("2024-03-20T15:10:05", json["since"]);
+        assert_eq!("312.4", json["deviation"].to_string());
+        assert!(json["timestamp"].is_string());
+    }
+
+    #[rstest]
+    #[case("text", LogFormat::Text)]
+    #[case("json", LogFormat::Json)]
+    fn log_format(#[case] s: &str, #[case] expected: LogFormat) {
+        assert_eq!(expected, s.parse().unwrap());
+    }
+}
@@ -0,0 +1,62 @@
+//! Identifiers shared by the static and realtime GTFS feeds, so consumers can join them.
+
+mod proto;
+mod realtime;
+mod static_feed;
+
+pub use realtime::{run as run_realtime, RealtimeFeed};
+pub use static_feed::StaticFeed;
+
+use chrono::NaiveDateTime;
+
+use crate::domain::{Ride, RouteDirection, Stop, Terminal};
+
+pub const AGENCY_ID: &str = "phuket-smart-bus";
+pub const SERVICE_ID: &str = "daily";
+
+/// Trips are identified by the schedule position and the departure time, e.g. `Bus1-0615`.
+pub fn trip_id(ride: &Ride) -> String {
+    format!("{}-{}", ride.name, ride.departure.format("%H%M"))
+}
+
+/// Stops without the sheet unique ID get one built from the direction and the order.
+pub fn stop_id(stop: &Stop) -> String {
+    stop.unique_id.map_or_else(
+        || format!("{}-{}", stop.route_direction, stop.order),
+        |id| id.to_string(),
+    )
+}
+
+/// Sheet and GPS times are local Phuket time, UTC+7 without daylight saving.
+pub const fn posix_time(date_time: NaiveDateTime) -> i64 {
+    date_time.and_utc().timestamp() - 7 * 60 * 60
+}
+
+/// Rides towards the airport go in direction 1 on every line, the ones away from it in 0.
+pub const fn direction_id(direction: RouteDirection) -> u8 {
+    match direction.0 {
+        Terminal::Airport => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+
+    #[test]
+    fn test_trip_id() {
+        let ride = Ride {
+            name: "Bus1".to_string(),
+            start: Terminal::Kata,
+            stop: Terminal::Airport,
+            loading: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(6, 15, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(8, 55, 0).unwrap(),
+        };
+
+        assert_eq!("Bus1-0615", trip_id(&ride));
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use prost::Message;
+
+use crate::{
+    events::Event,
+    pipeline::Pipeline,
+    services::{EtaService, RouteService, Vehicle, VehicleService},
+};
+
+use super::{
+    direction_id, posix_time,
+    proto::{
+        FeedEntity, FeedHeader, FeedMessage, Incrementality, Position, StopScheduleRelationship,
+        StopTimeEvent, StopTimeUpdate, TripDescriptor, TripScheduleRelationship, TripUpdate,
+        VehicleDescriptor, VehiclePosition, VehicleStopStatus,
+    },
+    stop_id, trip_id,
+};
+
+const GTFS_REALTIME_VERSION: &str = "2.0";
+
+/// Encoded GTFS-RT feeds, rebuilt from the pipeline state on every refresh.
+pub struct RealtimeFeed {
+    route_service: Arc<RouteService>,
+    eta_service: Arc<EtaService>,
+    vehicle_service: Arc<VehicleService>,
+    /// Buses not seen for this long are left out.
+    max_age: TimeDelta,
+    vehicle_positions: RwLock<Vec<u8>>,
+    trip_updates: RwLock<Vec<u8>>,
+}
+
+impl RealtimeFeed {
+    pub fn new(pipeline: &Pipeline, max_age: TimeDelta) -> Self {
+        Self {
+            route_service: pipeline.route_service.clone(),
+            eta_service: pipeline.eta_service.clone(),
+            vehicle_service: pipeline.vehicle_service.clone(),
+            max_age,
+            vehicle_positions: RwLock::default(),
+            trip_updates: RwLock::default(),
+        }
+    }
+
+    pub fn vehicle_positions(&self) -> Vec<u8> {
+        self.vehicle_positions.read().unwrap().clone()
+    }
+
+    pub fn trip_updates(&self) -> Vec<u8> {
+        self.trip_updates.read().unwrap().clone()
+    }
+
+    pub fn refresh(&self) {
+        self.refresh_at(Utc::now());
+    }
+
+    /// Rebuilds the feeds as of `now`.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn refresh_at(&self, now: DateTime<Utc>) {
+        let header = FeedHeader {
+            gtfs_realtime_version: GTFS_REALTIME_VERSION.to_string(),
+            incrementality: Some(Incrementality::FullDataset.into()),
+            timestamp: Some(now.timestamp() as u64),
+        };
+
+        let mut positions = vec![];
+        let mut updates = vec![];
+        for vehicle in self.vehicle_service.all() {
+            if now.timestamp() - posix_time(vehicle.date_time) > self.max_age.num_seconds() {
+                continue;
+            }
+            positions.push(FeedEntity {
+                id: vehicle.car_license.clone(),
+                is_deleted: None,
+                trip_update: None,
+                vehicle: Some(self.vehicle_position(&vehicle)),
+            });
+            if let Some(update) = self.trip_update(&vehicle) {
+                updates.push(FeedEntity {
+                    id: vehicle.car_license.clone(),
+                    is_deleted: None,
+                    trip_update: Some(update),
+                    vehicle: None,
+                });
+            }
+        }
+
+        *self.vehicle_positions.write().unwrap() = FeedMessage {
+            header: header.clone(),
+            entity: positions,
+        }
+        .encode_to_vec();
+        *self.trip_updates.write().unwrap() = FeedMessage {
+            header,
+            entity: updates,
+        }
+        .encode_to_vec();
+    }
+
+    pub fn write(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        // Write and rename, so readers never see a partially written feed.
+        for (name, data) in [
+            ("vehicle_positions.pb", self.vehicle_positions()),
+            ("trip_updates.pb", self.trip_updates()),
+        ] {
+            let temp = dir.join(format!(".{name}"));
+            std::fs::write(&temp, data)?;
+            std::fs::rename(temp, dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    #[allow(
+        clippy::cast_sign_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss
+    )]
+    fn vehicle_position(&self, vehicle: &Vehicle) -> VehiclePosition {
+        let next_stop = vehicle
+            .ride
+            .as_ref()
+            .zip(vehicle.next_stop.as_ref())
+            .and_then(|(ride, next)| {
+                self.route_service
+                    .timetable(ride)
+                    .into_iter()
+                    .enumerate()
+                    .find(|(_, (stop, _))| &stop.name == next)
+                    .map(|(index, (stop, _))| (index as u32 + 1, stop_id(&stop)))
+            });
+
+        VehiclePosition {
+            trip: self.trip_descriptor(vehicle),
+            position: Some(Position {
+                latitude: vehicle.coordinates.latitude.0,
+                longitude: vehicle.coordinates.longitude.0,
+                bearing: Some(vehicle.heading),
+                odometer: None,
+                speed: Some(vehicle.speed as f32 / 3.6),
+            }),
+            current_stop_sequence: next_stop.as_ref().map(|(sequence, _)| *sequence),
+            current_status: next_stop
+                .as_ref()
+                .map(|_| VehicleStopStatus::InTransitTo.into()),
+            timestamp: Some(posix_time(vehicle.date_time) as u64),
+            stop_id: next_stop.map(|(_, stop_id)| stop_id),
+            vehicle: Some(vehicle_descriptor(vehicle)),
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn trip_update(&self, vehicle: &Vehicle) -> Option<TripUpdate> {
+        let ride = vehicle.ride.as_ref()?;
+        let prediction = self.eta_service.prediction(&vehicle.car_license)?;
+        let timetable = self.route_service.timetable(ride);
+
+        let stop_time_update = prediction
+            .arrivals
+            .iter()
+            .filter_map(|(stop, time)| {
+                let (index, (_, scheduled)) = timetable
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (s, _))| s.name == stop.name)?;
+                let delay = (*time - time.date().and_time(*scheduled)).num_seconds();
+
+                Some(StopTimeUpdate {
+                    stop_sequence: Some(index as u32 + 1),
+                    arrival: Some(StopTimeEvent {
+                        delay: Some(delay as i32),
+                        time: Some(posix_time(*time)),
+                        uncertainty: None,
+                    }),
+                    departure: None,
+                    stop_id: Some(stop_id(stop)),
+                    schedule_relationship: Some(StopScheduleRelationship::Scheduled.into()),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Some(TripUpdate {
+            trip: self.trip_descriptor(vehicle)?,
+            delay: stop_time_update
+                .first()
+                .and_then(|u| u.arrival.as_ref())
+                .and_then(|a| a.delay),
+            stop_time_update,
+            vehicle: Some(vehicle_descriptor(vehicle)),
+            timestamp: Some(posix_time(vehicle.date_time) as u64),
+        })
+    }
+
+    fn trip_descriptor(&self, vehicle: &Vehicle) -> Option<TripDescriptor> {
+        let ride = vehicle.ride.as_ref()?;
+        Some(TripDescriptor {
+            trip_id: Some(trip_id(ride)),
+            start_time: Some(ride.departure.format("%H:%M:%S").to_string()),
+            start_date: Some(vehicle.date_time.format("%Y%m%d").to_string()),
+            schedule_relationship: Some(TripScheduleRelationship::Scheduled.into()),
+            route_id: self.route_service.line(ride).map(|line| line.id),
+            direction_id: vehicle.direction.map(|d| direction_id(d).into()),
+        })
+    }
+}
+
+/// Rebuilds the feeds every `interval` and, when `dir` is set, writes them to files.
+pub async fn run(feed: Arc<RealtimeFeed>, interval: Duration, dir: Option<PathBuf>) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        feed.refresh();
+        if let Some(dir) = &dir {
+            if let Err(err) = feed.write(dir) {
+                Event::failed("write GTFS-RT feed", &err).emit();
+            }
+        }
+    }
+}
+
+fn vehicle_descriptor(vehicle: &Vehicle) -> VehicleDescriptor {
+    VehicleDescriptor {
+        id: Some(vehicle.car_license.clone()),
+        label: vehicle.operate_position.clone(),
+        license_plate: Some(vehicle.car_license.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{HeadwayConfig, OffRouteConfig, StopConfig},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    const LOCATION: &str = r#"{"deviceno":"0088007439","lat":"8.089848","lng":"98.313305","state":1,"speed":36,"direction":160.2,"altitude":12,"dateTime":"2024-03-20 15:10:05","vid":246,"carlicense":"10-1152","groupName":"Phuket Smart Bus"}"#;
+    const NON_OPERATING: &str = r#"{"deviceno":"0000000000","lat":"7.884573","lng":"98.395432","state":1,"speed":0,"direction":0.0,"altitude":"-","dateTime":"2024-03-20 15:30:00","vid":999,"carlicense":"99-9999","groupName":"Phuket Smart Bus"}"#;
+
+    /// 2024-03-20 15:31:00 +07:00
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_710_923_460, 0).unwrap()
+    }
+
+    fn sut() -> RealtimeFeed {
+        let pipeline = Pipeline::new(
+            &Arc::new(FetchService::for_tests()),
+            OffRouteConfig::default(),
+            StopConfig::default(),
+            HeadwayConfig::default(),
+        );
+        pipeline.process_location_update(LOCATION);
+        pipeline.process_location_update(NON_OPERATING);
+
+        let feed = RealtimeFeed::new(&pipeline, TimeDelta::minutes(30));
+        feed.refresh_at(now());
+        feed
+    }
+
+    #[test]
+    fn vehicle_positions() {
+        let feed = FeedMessage::decode(sut().vehicle_positions().as_slice()).unwrap();
+
+        assert_eq!("2.0", feed.header.gtfs_realtime_version);
+        assert_eq!(2, feed.entity.len());
+
+        let vehicle = feed.entity[0].vehicle.as_ref().unwrap();
+        let trip = vehicle.trip.as_ref().unwrap();
+        assert_eq!(Some("Bus7-1500"), trip.trip_id.as_deref());
+        assert_eq!(Some("20240320"), trip.start_date.as_deref());
+        assert_eq!(Some(0), trip.direction_id);
+        assert_eq!(Some(2), vehicle.current_stop_sequence);
+        assert_eq!(Some("18"), vehicle.stop_id.as_deref());
+        assert_eq!(Some(10.0), vehicle.position.as_ref().unwrap().speed);
+        // 2024-03-20 15:10:05 +07:00
+        assert_eq!(Some(1_710_922_205), vehicle.timestamp);
+
+        let non_operating = feed.entity[1].vehicle.as_ref().unwrap();
+        assert!(non_operating.trip.is_none());
+    }
+
+    #[test]
+    fn skip_old_vehicles() {
+        let feed = sut();
+        feed.refresh_at(now() + TimeDelta::minutes(10));
+
+        let positions = FeedMessage::decode(feed.vehicle_positions().as_slice()).unwrap();
+        let trip_updates = FeedMessage::decode(feed.trip_updates().as_slice()).unwrap();
+        assert_eq!(
+            vec!["99-9999"],
+            positions.entity.iter().map(|e| &e.id).collect::<Vec<_>>()
+        );
+        assert!(trip_updates.entity.is_empty());
+    }
+
+    #[test]
+    fn trip_updates() {
+        let feed = FeedMessage::decode(sut().trip_updates().as_slice()).unwrap();
+
+        assert_eq!(1, feed.entity.len());
+
+        let update = feed.entity[0].trip_update.as_ref().unwrap();
+        assert_eq!(Some("Bus7-1500"), update.trip.trip_id.as_deref());
+        assert_eq!(26, update.stop_time_update.len());
+        assert_eq!(Some(2), update.stop_time_update[0].stop_sequence);
+        // Ten minutes after the departure, close to the terminal.
+        assert!(update.delay.unwrap() > 0);
+    }
+}
@@ -0,0 +1,301 @@
+// Field names follow the GTFS reference.
+#![allow(clippy::struct_field_names)]
+
+use std::{fs::File, path::Path};
+
+use chrono::{NaiveDate, NaiveTime};
+use serde::Serialize;
+
+use crate::{
+    domain::{Ride, Stop},
+    events::Event,
+    services::RouteService,
+};
+
+use super::{direction_id, stop_id, trip_id, AGENCY_ID, SERVICE_ID};
+
+#[derive(Debug, Serialize)]
+struct Agency {
+    agency_id: &'static str,
+    agency_name: &'static str,
+    agency_url: &'static str,
+    agency_timezone: &'static str,
+    agency_lang: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct GtfsStop {
+    stop_id: String,
+    stop_code: Option<usize>,
+    stop_name: String,
+    stop_desc: Option<String>,
+    stop_lat: f32,
+    stop_lon: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct Route {
+    route_id: String,
+    agency_id: &'static str,
+    route_short_name: &'static str,
+    route_long_name: String,
+    route_type: u8,
+    route_color: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Trip {
+    route_id: String,
+    service_id: &'static str,
+    trip_id: String,
+    trip_headsign: String,
+    direction_id: u8,
+}
+
+#[derive(Debug, Serialize)]
+struct StopTime {
+    trip_id: String,
+    arrival_time: String,
+    departure_time: String,
+    stop_id: String,
+    stop_sequence: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Calendar {
+    service_id: &'static str,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+/// GTFS static feed built from the sheet data.
+#[derive(Debug)]
+pub struct StaticFeed {
+    agency: Vec<Agency>,
+    stops: Vec<GtfsStop>,
+    routes: Vec<Route>,
+    trips: Vec<Trip>,
+    stop_times: Vec<StopTime>,
+    calendar: Vec<Calendar>,
+}
+
+impl StaticFeed {
+    /// Every ride runs daily within the `start..=end` service period.
+    pub fn build(
+        stops: &[Stop],
+        rides: &[Ride],
+        route_service: &RouteService,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Self {
+        let mut trips = Vec::with_capacity(rides.len());
+        let mut stop_times = vec![];
+
+        for ride in rides {
+            let timetable = route_service.timetable(ride);
+            let (Some(line), Some(direction)) =
+                (route_service.line(ride), route_service.direction(ride))
+            else {
+                Event::RideSkipped {
+                    ride: ride.to_string(),
+                    reason: "No line".to_string(),
+                }
+                .emit();
+                continue;
+            };
+            if timetable.is_empty() {
+                Event::RideSkipped {
+                    ride: ride.to_string(),
+                    reason: "No stops".to_string(),
+                }
+                .emit();
+                continue;
+            }
+
+            let trip_id = trip_id(ride);
+            stop_times.extend(
+                timetable
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, (stop, time))| StopTime {
+                        trip_id: trip_id.clone(),
+                        arrival_time: gtfs_time(time),
+                        departure_time: gtfs_time(time),
+                        stop_id: stop_id(&stop),
+                        stop_sequence: index + 1,
+                    }),
+            );
+            trips.push(Trip {
+                route_id: line.id,
+                service_id: SERVICE_ID,
+                trip_id,
+                trip_headsign: format!("to {}", ride.stop),
+                direction_id: direction_id(direction),
+            });
+        }
+
+        Self {
+            agency: vec![Agency {
+                agency_id: AGENCY_ID,
+                agency_name: "Phuket Smart Bus",
+                agency_url: "https://phuketsmartbus.com",
+                agency_timezone: "Asia/Bangkok",
+                agency_lang: "en",
+            }],
+            stops: stops
+                .iter()
+                .map(|stop| GtfsStop {
+                    stop_id: stop_id(stop),
+                    stop_code: stop.unique_id,
+                    stop_name: stop.name.clone(),
+                    stop_desc: stop.description.clone(),
+                    stop_lat: stop.coordinates.latitude.0,
+                    stop_lon: stop.coordinates.longitude.0,
+                })
+                .collect(),
+            routes: route_service
+                .lines()
+                .into_iter()
+                .map(|line| Route {
+                    route_id: line.id,
+                    agency_id: AGENCY_ID,
+                    route_short_name: "Smart Bus",
+                    route_long_name: line.name,
+                    route_type: 3, // Bus
+                    route_color: "CC8007",
+                })
+                .collect(),
+            trips,
+            stop_times,
+            calendar: vec![Calendar {
+                service_id: SERVICE_ID,
+                monday: 1,
+                tuesday: 1,
+                wednesday: 1,
+                thursday: 1,
+                friday: 1,
+                saturday: 1,
+                sunday: 1,
+                start_date: start.format("%Y%m%d").to_string(),
+                end_date: end.format("%Y%m%d").to_string(),
+            }],
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        write_file(&dir.join("agency.txt"), &self.agency)?;
+        write_file(&dir.join("stops.txt"), &self.stops)?;
+        write_file(&dir.join("routes.txt"), &self.routes)?;
+        write_file(&dir.join("trips.txt"), &self.trips)?;
+        write_file(&dir.join("stop_times.txt"), &self.stop_times)?;
+        write_file(&dir.join("calendar.txt"), &self.calendar)?;
+        Ok(())
+    }
+}
+
+fn write_file<T: Serialize>(path: &Path, rows: &[T]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(File::create(path)?);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn gtfs_time(time: NaiveTime) -> String {
+    time.format("%H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::services::FetchService;
+
+    use super::*;
+
+    fn sut() -> StaticFeed {
+        let fetch_service = Arc::new(FetchService::for_tests());
+        let route_service = RouteService::new(fetch_service.clone());
+        let rides = fetch_service
+            .schedule()
+            .into_iter()
+            .map(Ride::from)
+            .collect::<Vec<_>>();
+
+        StaticFeed::build(
+            &fetch_service.stops(),
+            &rides,
+            &route_service,
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2025, 2, 28).unwrap(),
+        )
+    }
+
+    #[test]
+    fn build() {
+        let feed = sut();
+
+        assert_eq!(52, feed.stops.len());
+        assert_eq!(34, feed.trips.len());
+        assert_eq!(
+            vec![
+                "airport-rawai",
+                "airport-kata",
+                "airport-patong",
+                "rawai-patong"
+            ],
+            feed.routes
+                .iter()
+                .map(|r| r.route_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("20240301", feed.calendar[0].start_date);
+
+        let kata_airport = feed
+            .stop_times
+            .iter()
+            .filter(|st| st.trip_id == "Bus1-0615")
+            .collect::<Vec<_>>();
+        assert_eq!(23, kata_airport.len());
+        assert_eq!("06:15:00", kata_airport.first().unwrap().departure_time);
+        assert_eq!("08:55:00", kata_airport.last().unwrap().arrival_time);
+        assert_eq!(23, kata_airport.last().unwrap().stop_sequence);
+    }
+
+    #[test]
+    fn stop_times_reference_stops() {
+        let feed = sut();
+
+        assert!(feed
+            .stop_times
+            .iter()
+            .all(|st| feed.stops.iter().any(|s| s.stop_id == st.stop_id)));
+        assert!(feed
+            .stop_times
+            .iter()
+            .all(|st| feed.trips.iter().any(|t| t.trip_id == st.trip_id)));
+    }
+
+    #[test]
+    fn write() {
+        let dir = std::env::temp_dir().join(format!("gtfs-{}", std::process::id()));
+
+        sut().write(&dir).unwrap();
+        let trips = std::fs::read_to_string(dir.join("trips.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(trips.starts_with("route_id,service_id,trip_id,trip_headsign,direction_id\n"));
+        assert!(trips.contains("airport-kata,daily,Bus1-0615,to Airport,1\n"));
+    }
+}
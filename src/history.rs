@@ -0,0 +1,487 @@
+use std::{
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, TimeDelta};
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+
+use crate::{
+    domain::{phuket_now, Coordinates, Latitude, Longitude},
+    events::Event,
+    services::{StopVisit, Vehicle},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS locations (
+    car_license TEXT NOT NULL,
+    date_time TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    speed INTEGER NOT NULL,
+    heading REAL NOT NULL,
+    altitude INTEGER NOT NULL,
+    operate_position TEXT,
+    ride TEXT,
+    previous_stop TEXT,
+    next_stop TEXT,
+    raw_latitude REAL,
+    raw_longitude REAL,
+    PRIMARY KEY (car_license, date_time)
+);
+CREATE INDEX IF NOT EXISTS locations_date_time ON locations (date_time);
+CREATE TABLE IF NOT EXISTS stop_visits (
+    car_license TEXT NOT NULL,
+    ride TEXT NOT NULL,
+    stop TEXT NOT NULL,
+    arrived_at TEXT NOT NULL,
+    departed_at TEXT NOT NULL,
+    PRIMARY KEY (car_license, arrived_at)
+);
+CREATE INDEX IF NOT EXISTS stop_visits_stop ON stop_visits (stop, arrived_at);
+";
+
+/// Columns added to the tables after their creation, for the databases created before.
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("locations", "raw_latitude", "REAL"),
+    ("locations", "raw_longitude", "REAL"),
+];
+
+/// A deduplicated location of a bus with everything the pipeline has matched for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub car_license: String,
+    pub date_time: NaiveDateTime,
+    /// Smoothed.
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed: u32,
+    pub heading: f32,
+    pub altitude: u32,
+    pub operate_position: Option<String>,
+    pub ride: Option<String>,
+    pub previous_stop: Option<String>,
+    pub next_stop: Option<String>,
+    /// As reported by the bus, not stored before the locations were smoothed.
+    pub raw_latitude: Option<f64>,
+    pub raw_longitude: Option<f64>,
+}
+
+impl From<&Vehicle> for HistoryEntry {
+    fn from(vehicle: &Vehicle) -> Self {
+        Self {
+            car_license: vehicle.car_license.clone(),
+            date_time: vehicle.date_time,
+            latitude: vehicle.coordinates.latitude.0.into(),
+            longitude: vehicle.coordinates.longitude.0.into(),
+            speed: vehicle.speed,
+            heading: vehicle.heading,
+            altitude: vehicle.altitude,
+            operate_position: vehicle.operate_position.clone(),
+            ride: vehicle.ride.as_ref().map(|r| r.name.clone()),
+            previous_stop: vehicle.previous_stop.clone(),
+            next_stop: vehicle.next_stop.clone(),
+            raw_latitude: Some(vehicle.raw_coordinates.latitude.0.into()),
+            raw_longitude: Some(vehicle.raw_coordinates.longitude.0.into()),
+        }
+    }
+}
+
+impl HistoryEntry {
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn coordinates(&self) -> Coordinates {
+        Coordinates::new(
+            Longitude(self.longitude as f32),
+            Latitude(self.latitude as f32),
+        )
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            car_license: row.get(0)?,
+            date_time: row.get(1)?,
+            latitude: row.get(2)?,
+            longitude: row.get(3)?,
+            speed: row.get(4)?,
+            heading: row.get(5)?,
+            altitude: row.get(6)?,
+            operate_position: row.get(7)?,
+            ride: row.get(8)?,
+            previous_stop: row.get(9)?,
+            next_stop: row.get(10)?,
+            raw_latitude: row.get(11)?,
+            raw_longitude: row.get(12)?,
+        })
+    }
+}
+
+/// Location history kept in a local `SQLite` database.
+pub struct History {
+    connection: Mutex<Connection>,
+    /// How long the entries are kept, forever if not set.
+    retention: Option<TimeDelta>,
+}
+
+impl History {
+    pub fn open<P: AsRef<Path>>(path: P, retention: Option<TimeDelta>) -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open(path)?, retention)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory(retention: Option<TimeDelta>) -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, retention)
+    }
+
+    fn with_connection(
+        connection: Connection,
+        retention: Option<TimeDelta>,
+    ) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        for (table, column, kind) in MIGRATIONS {
+            let exists = connection
+                .prepare(&format!(
+                    "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+                ))?
+                .exists([column])?;
+            if !exists {
+                connection
+                    .execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))?;
+            }
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+            retention,
+        })
+    }
+
+    pub fn insert(&self, entry: &HistoryEntry) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO locations
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                entry.car_license,
+                entry.date_time,
+                entry.latitude,
+                entry.longitude,
+                entry.speed,
+                entry.heading,
+                entry.altitude,
+                entry.operate_position,
+                entry.ride,
+                entry.previous_stop,
+                entry.next_stop,
+                entry.raw_latitude,
+                entry.raw_longitude,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_visit(&self, visit: &StopVisit) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO stop_visits VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                visit.car_license,
+                visit.ride,
+                visit.stop,
+                visit.arrived_at,
+                visit.departed_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Visits of the stop by any bus arrived between `from` and `to` inclusive, ordered by time.
+    pub fn visits(
+        &self,
+        stop: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> anyhow::Result<Vec<StopVisit>> {
+        let connection = self.connection.lock().unwrap();
+        let visits = connection
+            .prepare_cached(
+                "SELECT * FROM stop_visits
+                 WHERE stop = ?1 AND arrived_at BETWEEN ?2 AND ?3
+                 ORDER BY arrived_at",
+            )?
+            .query_map(params![stop, from, to], |row| {
+                Ok(StopVisit {
+                    car_license: row.get(0)?,
+                    ride: row.get(1)?,
+                    stop: row.get(2)?,
+                    arrived_at: row.get(3)?,
+                    departed_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        drop(connection);
+        Ok(visits)
+    }
+
+    /// Locations of the bus between `from` and `to` inclusive, ordered by time.
+    pub fn history(
+        &self,
+        car_license: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> anyhow::Result<Vec<HistoryEntry>> {
+        let connection = self.connection.lock().unwrap();
+        let entries = connection
+            .prepare_cached(
+                "SELECT * FROM locations
+                 WHERE car_license = ?1 AND date_time BETWEEN ?2 AND ?3
+                 ORDER BY date_time",
+            )?
+            .query_map(params![car_license, from, to], HistoryEntry::from_row)?
+            .collect::<Result<_, _>>()?;
+        drop(connection);
+        Ok(entries)
+    }
+
+    /// Deletes the entries older than the retention period before `now`.
+    pub fn purge(&self, now: NaiveDateTime) -> anyhow::Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let connection = self.connection.lock().unwrap();
+        let deleted = connection.execute(
+            "DELETE FROM locations WHERE date_time < ?1",
+            params![now - retention],
+        )?;
+        connection.execute(
+            "DELETE FROM stop_visits WHERE arrived_at < ?1",
+            params![now - retention],
+        )?;
+        drop(connection);
+        Ok(deleted)
+    }
+}
+
+/// A write queued for [`HistoryWriter`].
+enum Write {
+    Location(HistoryEntry),
+    Visit(StopVisit),
+    /// Answers once the writes queued before are done.
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+/// Queues the writes to the history for a dedicated thread, so processing the locations
+/// never waits for the disk.
+#[derive(Clone)]
+pub struct HistoryWriter {
+    sender: mpsc::Sender<Write>,
+}
+
+impl HistoryWriter {
+    /// Starts the thread, which runs until every clone of the writer is dropped.
+    pub fn spawn(history: Arc<History>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("history-writer".to_string())
+            .spawn(move || {
+                for write in receiver {
+                    match write {
+                        Write::Location(entry) => {
+                            if let Err(err) = history.insert(&entry) {
+                                Event::failed("store location", &err).emit();
+                            }
+                        }
+                        Write::Visit(visit) => {
+                            if let Err(err) = history.insert_visit(&visit) {
+                                Event::failed("store stop visit", &err).emit();
+                            }
+                        }
+                        #[cfg(test)]
+                        Write::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("Spawned history writer");
+        Self { sender }
+    }
+
+    pub fn insert(&self, entry: HistoryEntry) {
+        // The thread lives as long as the sender.
+        let _ = self.sender.send(Write::Location(entry));
+    }
+
+    pub fn insert_visit(&self, visit: StopVisit) {
+        let _ = self.sender.send(Write::Visit(visit));
+    }
+
+    /// Waits for the queued writes.
+    #[cfg(test)]
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        self.sender.send(Write::Flush(done)).unwrap();
+        wait.recv().unwrap();
+    }
+}
+
+/// Purges the history periodically.
+pub async fn run_retention(history: Arc<History>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match history.purge(phuket_now()) {
+            Ok(0) => {}
+            Ok(deleted) => Event::HistoryPurged { deleted }.emit(),
+            Err(err) => Event::failed("purge history", &err).emit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn entry(car_license: &str, hour: u32, minute: u32) -> HistoryEntry {
+        HistoryEntry {
+            car_license: car_license.to_string(),
+            date_time: NaiveDate::from_ymd_opt(2024, 3, 20)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap(),
+            latitude: 8.089_848,
+            longitude: 98.313_305,
+            speed: 38,
+            heading: 160.2,
+            altitude: 12,
+            operate_position: Some("Bus7".to_string()),
+            ride: Some("Bus7".to_string()),
+            previous_stop: Some("Phuket Airport".to_string()),
+            next_stop: Some("Thalang Public Health Office".to_string()),
+            raw_latitude: Some(8.089_9),
+            raw_longitude: Some(98.313_3),
+        }
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn history() {
+        let sut = History::in_memory(None).unwrap();
+        for entry in [
+            entry("10-1152", 14, 0),
+            entry("10-1152", 14, 5),
+            entry("10-1152", 14, 10),
+            entry("10-1153", 14, 5),
+        ] {
+            sut.insert(&entry).unwrap();
+        }
+        // Duplicates are replaced.
+        sut.insert(&entry("10-1152", 14, 5)).unwrap();
+
+        let history = sut.history("10-1152", time(14, 1), time(14, 10)).unwrap();
+
+        assert_eq!(
+            vec![entry("10-1152", 14, 5), entry("10-1152", 14, 10)],
+            history
+        );
+        assert!(sut
+            .history("10-1152", time(15, 0), time(16, 0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn migrate() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE locations (
+                    car_license TEXT NOT NULL,
+                    date_time TEXT NOT NULL,
+                    latitude REAL NOT NULL,
+                    longitude REAL NOT NULL,
+                    speed INTEGER NOT NULL,
+                    heading REAL NOT NULL,
+                    altitude INTEGER NOT NULL,
+                    operate_position TEXT,
+                    ride TEXT,
+                    previous_stop TEXT,
+                    next_stop TEXT,
+                    PRIMARY KEY (car_license, date_time)
+                );",
+            )
+            .unwrap();
+        let sut = History::with_connection(connection, None).unwrap();
+
+        sut.insert(&entry("10-1152", 14, 0)).unwrap();
+
+        assert_eq!(
+            vec![entry("10-1152", 14, 0)],
+            sut.history("10-1152", time(14, 0), time(14, 0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn visits() {
+        let sut = History::in_memory(TimeDelta::try_hours(1)).unwrap();
+        let visit = |car_license: &str, stop: &str, minute: u32| StopVisit {
+            car_license: car_license.to_string(),
+            ride: "Bus7".to_string(),
+            stop: stop.to_string(),
+            arrived_at: time(14, minute),
+            departed_at: time(14, minute + 2),
+        };
+        for visit in [
+            visit("10-1152", "Bangla Patong", 0),
+            visit("10-1153", "Bangla Patong", 10),
+            visit("10-1152", "Central Festival", 20),
+        ] {
+            sut.insert_visit(&visit).unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                visit("10-1152", "Bangla Patong", 0),
+                visit("10-1153", "Bangla Patong", 10)
+            ],
+            sut.visits("Bangla Patong", time(14, 0), time(15, 0))
+                .unwrap()
+        );
+
+        sut.purge(time(15, 5)).unwrap();
+        assert_eq!(
+            1,
+            sut.visits("Bangla Patong", time(14, 0), time(15, 0))
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn purge() {
+        let sut = History::in_memory(TimeDelta::try_hours(1)).unwrap();
+        sut.insert(&entry("10-1152", 12, 0)).unwrap();
+        sut.insert(&entry("10-1152", 14, 0)).unwrap();
+
+        assert_eq!(1, sut.purge(time(14, 30)).unwrap());
+        assert_eq!(
+            vec![entry("10-1152", 14, 0)],
+            sut.history("10-1152", time(0, 0), time(23, 59)).unwrap()
+        );
+
+        assert_eq!(
+            0,
+            History::in_memory(None)
+                .unwrap()
+                .purge(time(14, 30))
+                .unwrap()
+        );
+    }
+}
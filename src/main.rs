@@ -1,160 +1,408 @@
-use std::{
-    collections::HashMap,
-    env::args,
-    sync::{Arc, Mutex},
-};
+use std::{env::args, path::Path, sync::Arc};
 
-use chrono::NaiveDateTime;
+use anyhow::anyhow;
 use config::Config;
 use futures_util::FutureExt;
 use rust_socketio::{asynchronous::ClientBuilder, Event, Payload};
 use tokio::signal;
 
+mod api;
+mod board;
 mod config;
+mod data_diff;
+mod data_source;
 mod domain;
+mod events;
+mod gtfs;
+mod history;
+mod metrics;
+mod pipeline;
+mod recording;
 mod services;
+mod trips;
+mod validation;
 
-use domain::Location;
-use services::{BusService, FetchService, RideService, RouteService};
+use data_source::DataSource;
+use history::History;
+use pipeline::Pipeline;
+use recording::{Recorder, ReplaySpeed};
+use services::{FetchService, RouteService};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let config = Config::load()?;
+    let args = args().skip(1).collect::<Vec<_>>();
+    // The board takes the whole terminal.
+    events::init(if args.first().is_some_and(|a| a == "board") {
+        events::LogFormat::Off
+    } else {
+        config.log_format
+    });
 
-    if args().nth(1).as_deref() == Some("fetch") {
-        return fetch_test_data(&config);
-    }
+    let recorder = match args.first().map(String::as_str) {
+        Some("fetch") => return fetch_test_data(&config),
+        Some("export-gtfs") => {
+            let dir = args
+                .get(1)
+                .ok_or_else(|| anyhow!("Usage: export-gtfs <dir>"))?;
+            return export_gtfs(config, dir);
+        }
+        Some("replay") => {
+            let path = args
+                .get(1)
+                .ok_or_else(|| anyhow!("Usage: replay <file> [real|max|<factor>x]"))?;
+            let speed = args.get(2).map_or(Ok(ReplaySpeed::Real), |s| s.parse())?;
+            return replay(config, path, speed).await;
+        }
+        Some("history") => {
+            let usage =
+                || anyhow!("Usage: history <license> <from> <to>, e.g. 2024-03-20T14:00:00");
+            let license = args.get(1).ok_or_else(usage)?;
+            let from = args.get(2).ok_or_else(usage)?.parse()?;
+            let to = args.get(3).ok_or_else(usage)?.parse()?;
+            return print_history(&config, license, from, to);
+        }
+        Some("trips") => {
+            let usage = || anyhow!("Usage: trips <license> <from> <to>, e.g. 2024-03-20T00:00:00");
+            let license = args.get(1).ok_or_else(usage)?;
+            let from = args.get(2).ok_or_else(usage)?.parse()?;
+            let to = args.get(3).ok_or_else(usage)?.parse()?;
+            return print_trips(config, license, from, to);
+        }
+        Some("validate") => return validate(&config),
+        Some("board") => {
+            let stop = args
+                .get(1)
+                .ok_or_else(|| anyhow!("Usage: board <stop name or ID>"))?;
+            return board(config, stop).await;
+        }
+        Some("record") => {
+            let path = args.get(1).ok_or_else(|| anyhow!("Usage: record <file>"))?;
+            println!("Recording to {path}");
+            Some(Arc::new(Recorder::create(path)?))
+        }
+        _ => None,
+    };
 
     let fetch_service = Arc::new(FetchService::new(config.clone()));
-    let bus_service = Arc::new(BusService::new(fetch_service.clone()));
-    let ride_service = Arc::new(RideService::new(fetch_service.clone()));
-    let route_service = Arc::new(RouteService::new(fetch_service.clone()));
+    // Locations are matched against the data, so it must be there before connecting.
+    fetch_service.refresh()?;
+    tokio::spawn(services::run_refresh(fetch_service.clone()));
+    let pipeline = Arc::new(with_history(
+        &config,
+        Pipeline::new(
+            &fetch_service,
+            config.off_route,
+            config.stops,
+            config.headway,
+        ),
+    )?);
 
-    let bus_lru = Arc::new(Mutex::new(HashMap::with_capacity(
-        bus_service.number_of_buses(),
-    )));
+    let realtime_feed = Arc::new(gtfs::RealtimeFeed::new(&pipeline, config.gtfs_rt_max_age));
+    tokio::spawn(gtfs::run_realtime(
+        realtime_feed.clone(),
+        config.gtfs_rt_refresh,
+        config.gtfs_rt_dir.clone().map(Into::into),
+    ));
 
-    ClientBuilder::new(config.app_socket)
+    if let Some(addr) = config.http_bind.clone() {
+        let router = api::router(fetch_service.clone(), pipeline.clone(), realtime_feed);
+        tokio::spawn(async move {
+            if let Err(err) = api::serve(&addr, router).await {
+                events::Event::failed("serve HTTP API", &err).emit();
+            }
+        });
+    }
+
+    connect(&config.app_socket, pipeline, recorder).await;
+
+    signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// Feeds the locations from the socket into the pipeline.
+async fn connect(app_socket: &str, pipeline: Arc<Pipeline>, recorder: Option<Arc<Recorder>>) {
+    ClientBuilder::new(app_socket)
         .namespace("/")
         .on_any(move |event, payload, _client| {
-            let bus_service = bus_service.clone();
-            let ride_service = ride_service.clone();
-            let route_service = route_service.clone();
-            let bus_lru = bus_lru.clone();
-            async move {
-                match event {
-                    Event::Connect => println!("Connected"),
-                    Event::Close => println!("Disconnected"),
-                    Event::Error => eprintln!("Error: {payload:?}"),
-                    Event::Message => println!("Message: {payload:?}"),
-                    Event::Custom(custom) => match custom.as_ref() {
-                        "sub_gps" => match payload {
-                            Payload::Binary(bin) => println!("{custom} bin: {bin:?}"),
-                            Payload::String(value) => process_location_update(
-                                &value,
-                                &bus_lru,
-                                &bus_service,
-                                &ride_service,
-                                &route_service,
-                            ),
-                        },
-                        _ => println!("{custom}: {payload:?}"),
-                    },
-                }
-            }
-            .boxed()
+            let pipeline = pipeline.clone();
+            let recorder = recorder.clone();
+            async move { on_event(event, payload, &pipeline, recorder.as_deref()) }.boxed()
         })
         .connect()
         .await
         .expect("Connection failed");
+}
 
-    signal::ctrl_c().await?;
+/// Prints the arrivals at the stop, refreshing them in place until interrupted.
+async fn board(config: Config, query: &str) -> anyhow::Result<()> {
+    let fetch_service = Arc::new(FetchService::new(config.clone()));
+    fetch_service.refresh()?;
+    let name = board::find_stops(&fetch_service.stops(), query)
+        .first()
+        .map(|s| s.name.clone())
+        .ok_or_else(|| anyhow!("No stop {query}"))?;
+
+    tokio::spawn(services::run_refresh(fetch_service.clone()));
+    let pipeline = Arc::new(Pipeline::new(
+        &fetch_service,
+        config.off_route,
+        config.stops,
+        config.headway,
+    ));
+    connect(&config.app_socket, pipeline.clone(), None).await;
+
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(10));
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            result = signal::ctrl_c() => return Ok(result?),
+        }
+        let now = domain::phuket_now();
+        // The stops and their schedules change with the refreshed data.
+        let stops = board::find_stops(&fetch_service.stops(), query);
+        let directions = stops
+            .iter()
+            .map(|stop| {
+                let direction = domain::RouteDirection::from(stop.route_direction);
+                let predictions = pipeline.eta_service.eta(&stop.name, direction);
+                (direction, board::arrivals(stop, &predictions, now))
+            })
+            .collect::<Vec<_>>();
+        // Clears the terminal to print the board over the previous one.
+        print!("\x1b[2J\x1b[H{}", board::render(&name, &directions, now));
+    }
+}
 
+async fn replay(config: Config, path: &str, speed: ReplaySpeed) -> anyhow::Result<()> {
+    let records = recording::read_records(path)?;
+    println!(
+        "Replaying {} records from {path}, speed={speed:?}",
+        records.len()
+    );
+
+    let fetch_service = Arc::new(FetchService::new(config.clone()));
+    fetch_service.refresh()?;
+    let pipeline = Pipeline::new(
+        &fetch_service,
+        config.off_route,
+        config.stops,
+        config.headway,
+    );
+
+    let count = recording::replay(&records, speed, |payload| {
+        pipeline.process_location_update(payload);
+    })
+    .await;
+
+    println!("Replay completed, {count} records");
     Ok(())
 }
 
-fn process_location_update(
-    value: &str,
-    bus_lru: &Mutex<HashMap<String, NaiveDateTime>>,
-    bus_service: &BusService,
-    ride_service: &RideService,
-    route_service: &RouteService,
-) {
-    let location = match serde_json::from_str::<Location>(value) {
-        Ok(value) => value,
-        Err(err) => {
-            eprintln!("ERROR Failed to parse, {err:#}\n{value}");
-            return;
-        }
-    };
+fn export_gtfs(config: Config, dir: &str) -> anyhow::Result<()> {
+    let fetch_service = Arc::new(FetchService::new(config));
+    fetch_service.refresh()?;
+    let route_service = RouteService::new(fetch_service.clone());
+    let rides = fetch_service
+        .schedule()
+        .into_iter()
+        .map(domain::Ride::from)
+        .collect::<Vec<_>>();
+
+    let start = domain::phuket_now().date();
+    let end = start + chrono::TimeDelta::try_days(365).unwrap();
+
+    gtfs::StaticFeed::build(&fetch_service.stops(), &rides, &route_service, start, end)
+        .write(dir)?;
+    println!("GTFS feed exported to {dir}");
+
+    Ok(())
+}
+
+fn on_event(event: Event, payload: Payload, pipeline: &Pipeline, recorder: Option<&Recorder>) {
+    metrics::METRICS
+        .messages_received
+        .with_label_values(&[event.as_str()])
+        .inc();
 
-    if let Some(last_date_time) = bus_lru
-        .lock()
-        .unwrap()
-        .insert(location.car_license.clone(), location.date_time)
-    {
-        if last_date_time == location.date_time {
-            // duplicating message, skip
-            return;
+    match event {
+        Event::Connect => events::Event::Connected.emit(),
+        Event::Close => events::Event::Disconnected.emit(),
+        Event::Error => events::Event::SocketError {
+            error: format!("{payload:?}"),
+        }
+        .emit(),
+        Event::Custom(custom) if custom == "sub_gps" => match payload {
+            Payload::String(value) => {
+                if let Some(recorder) = recorder {
+                    if let Err(err) = recorder.record(&value) {
+                        events::Event::failed("record", &err).emit();
+                    }
+                }
+                pipeline.process_location_update(&value);
+            }
+            Payload::Binary(bin) => events::Event::UnknownMessage {
+                name: custom,
+                payload: format!("{bin:?}"),
+            }
+            .emit(),
+        },
+        Event::Custom(name) => events::Event::UnknownMessage {
+            name,
+            payload: format!("{payload:?}"),
+        }
+        .emit(),
+        Event::Message => events::Event::UnknownMessage {
+            name: "message".to_string(),
+            payload: format!("{payload:?}"),
         }
+        .emit(),
     }
+}
 
-    let Some(bus) = bus_service.operate_position(&location.car_license) else {
-        eprintln!("WARN Non-operating bus, license={}", location.car_license);
-        return;
+fn with_history(config: &Config, pipeline: Pipeline) -> anyhow::Result<Pipeline> {
+    let Some(path) = &config.history_db else {
+        return Ok(pipeline);
     };
 
-    let Some(ride) = ride_service.get(&bus, location.date_time.time()) else {
-        eprintln!(
-            "WARN Non-operating bus, position={}, license={}",
-            bus, location.car_license
+    let history = Arc::new(History::open(path, config.history_retention)?);
+    tokio::spawn(history::run_retention(
+        history.clone(),
+        std::time::Duration::from_hours(1),
+    ));
+    Ok(pipeline.with_history(history))
+}
+
+fn print_history(
+    config: &Config,
+    license: &str,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> anyhow::Result<()> {
+    let path = config
+        .history_db
+        .as_ref()
+        .ok_or_else(|| anyhow!("History is disabled, set history_db"))?;
+
+    for entry in History::open(path, None)?.history(license, from, to)? {
+        println!(
+            "{}\t{}\t{},{}\t{}kmh\t{} => {}",
+            entry.date_time,
+            entry.ride.as_deref().unwrap_or("-"),
+            entry.latitude,
+            entry.longitude,
+            entry.speed,
+            entry.previous_stop.as_deref().unwrap_or("-"),
+            entry.next_stop.as_deref().unwrap_or("-")
         );
-        return;
-    };
+    }
+    Ok(())
+}
+
+fn print_trips(
+    config: Config,
+    license: &str,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> anyhow::Result<()> {
+    let path = config
+        .history_db
+        .clone()
+        .ok_or_else(|| anyhow!("History is disabled, set history_db"))?;
+    let entries = History::open(path, None)?.history(license, from, to)?;
+    let locations = entries
+        .iter()
+        .map(|entry| (entry.date_time, entry.coordinates()))
+        .collect::<Vec<_>>();
+    let positions = entries
+        .into_iter()
+        .map(|entry| (entry.date_time, entry.operate_position))
+        .collect::<Vec<_>>();
+
+    let fetch_service = Arc::new(FetchService::new(config));
+    fetch_service.refresh()?;
+    let snapshot = fetch_service.snapshot();
+    let network = domain::Network::build(
+        &snapshot.stops,
+        snapshot.schedule.iter().map(|s| (s.start, s.destination)),
+    );
 
-    if let Some((prev, next)) = route_service.locate(ride.direction(), location.coordinates) {
+    let mut trips = trips::reconstruct(license, &locations, &snapshot.stops, &network);
+    trips::assign_positions(&mut trips, &positions);
+    // Rides of the positions the bus was recorded driving, it may have been reassigned since.
+    let ride_service = services::RideService::new(fetch_service);
+    let rides = trips
+        .iter()
+        .filter_map(|t| t.position.clone())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .flat_map(|position| ride_service.rides(&position))
+        .collect::<Vec<_>>();
+    let missed = trips::match_rides(&mut trips, &rides, from, to);
+    for trip in &trips {
         println!(
-            "{}\t{}\t{} => {}, {}m from {} => {}m to {}, speed={}kmh, heading={}°, altitude={}m",
-            location.date_time,
-            ride.name,
-            ride.start,
-            ride.stop,
-            prev.coordinates.distance_to(location.coordinates),
-            prev.name,
-            next.coordinates.distance_to(location.coordinates),
-            next.name,
-            location.speed,
-            location.heading.0,
-            location.altitude
+            "{} - {}\t{} -> {}\t{:.1}km\t{} stops\t{}",
+            trip.started_at,
+            trip.ended_at.time(),
+            trip.start,
+            trip.stop,
+            trip.distance / 1000.0,
+            trip.stops.len(),
+            trip.ride
+                .as_ref()
+                .map_or_else(|| "no ride".to_string(), ToString::to_string)
         );
-    } else {
-        eprintln!(
-            "WARN {}\t{} => {}, can't match location {}",
-            ride.name, ride.start, ride.stop, location.coordinates
+    }
+    for missed in &missed {
+        println!("{}\tno trip for {}", missed.departure, missed.ride);
+    }
+    Ok(())
+}
+
+fn validate(config: &Config) -> anyhow::Result<()> {
+    let reports = validation::validate(
+        data_source::from_config(config).as_ref(),
+        &config.column_aliases,
+    )?;
+    for report in &reports {
+        println!(
+            "{:?}: {} rows parsed, {} rejected",
+            report.dataset,
+            report.parsed,
+            report.rejections.len()
         );
+        for rejection in &report.rejections {
+            println!(
+                "  row {}, column {}: {}, got {:?}",
+                rejection.row,
+                rejection.column.as_deref().unwrap_or("-"),
+                rejection.reason,
+                rejection.value
+            );
+        }
     }
+
+    let rejected = reports.iter().map(|r| r.rejections.len()).sum::<usize>();
+    anyhow::ensure!(rejected == 0, "{rejected} rows rejected");
+    Ok(())
 }
 
 fn fetch_test_data(config: &Config) -> anyhow::Result<()> {
     println!("Fetching test data");
 
-    std::io::copy(
-        &mut ureq::get(&config.buses_url).call()?.into_reader(),
-        &mut std::fs::File::create("data/buses.json")?,
-    )?;
-    println!("Buses OK");
-
-    std::io::copy(
-        &mut ureq::get(&config.schedule_url).call()?.into_reader(),
-        &mut std::fs::File::create("data/schedule.json")?,
-    )?;
-    println!("Schedule OK");
-
-    std::io::copy(
-        &mut ureq::get(&config.stops_url).call()?.into_reader(),
-        &mut std::fs::File::create("data/stops.json")?,
-    )?;
-    println!("Stops OK");
+    let source = data_source::SheetsSource {
+        buses: config.buses_url.clone(),
+        schedule: config.schedule_url.clone(),
+        stops: config.stops_url.clone(),
+    };
+    for dataset in data_source::Dataset::ALL {
+        std::io::copy(
+            &mut source.open(dataset)?,
+            &mut std::fs::File::create(Path::new("data").join(dataset.file_name()))?,
+        )?;
+        println!("{dataset:?} OK");
+    }
 
     Ok(())
 }
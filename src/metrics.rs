@@ -0,0 +1,171 @@
+use std::sync::LazyLock;
+
+use chrono::Utc;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+/// Metrics of the running service, shared like the log of [`crate::events`].
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+pub struct Metrics {
+    registry: Registry,
+    /// Socket messages by event name, e.g. `sub_gps`.
+    pub messages_received: IntCounterVec,
+    pub duplicates_skipped: IntCounter,
+    pub parse_failures: IntCounter,
+    /// Locations rejected by [`crate::services::FilterService`] by `reason`.
+    pub rejected_locations: IntCounterVec,
+    /// Locations of buses without an operate position or a ride at the time, by `reason`.
+    pub non_operating_buses: IntCounterVec,
+    pub unmatched_locations: IntCounter,
+    /// Data refreshes of [`crate::services::FetchService`] by `result`.
+    pub data_refreshes: IntCounterVec,
+    pub data_version: IntGauge,
+    last_refresh: IntGauge,
+    data_age: IntGauge,
+    pub process_location_update: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("smart_bus".to_string()), None)
+            .expect("Valid registry prefix");
+
+        let metrics = Self {
+            messages_received: IntCounterVec::new(
+                Opts::new("messages_received_total", "Socket messages by event"),
+                &["event"],
+            )
+            .unwrap(),
+            duplicates_skipped: IntCounter::new(
+                "duplicates_skipped_total",
+                "Locations skipped as duplicates of the last one of the bus",
+            )
+            .unwrap(),
+            parse_failures: IntCounter::new(
+                "parse_failures_total",
+                "Location payloads failed to parse",
+            )
+            .unwrap(),
+            rejected_locations: IntCounterVec::new(
+                Opts::new(
+                    "rejected_locations_total",
+                    "Locations rejected as outliers by reason",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            non_operating_buses: IntCounterVec::new(
+                Opts::new(
+                    "non_operating_buses_total",
+                    "Locations of buses not operating a ride",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            unmatched_locations: IntCounter::new(
+                "unmatched_locations_total",
+                "Locations failed to match to the route of the ride",
+            )
+            .unwrap(),
+            data_refreshes: IntCounterVec::new(
+                Opts::new("data_refreshes_total", "Data refreshes by result"),
+                &["result"],
+            )
+            .unwrap(),
+            data_version: IntGauge::new("data_version", "Current data version").unwrap(),
+            last_refresh: IntGauge::new(
+                "data_last_refresh_timestamp_seconds",
+                "Time of the last successful data refresh",
+            )
+            .unwrap(),
+            data_age: IntGauge::new(
+                "data_age_seconds",
+                "Time since the last successful data refresh",
+            )
+            .unwrap(),
+            process_location_update: Histogram::with_opts(
+                HistogramOpts::new(
+                    "process_location_update_seconds",
+                    "Processing time of a location update",
+                )
+                .buckets(prometheus::exponential_buckets(0.000_05, 2.0, 14).unwrap()),
+            )
+            .unwrap(),
+            registry,
+        };
+
+        for collector in [
+            Box::new(metrics.messages_received.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.duplicates_skipped.clone()),
+            Box::new(metrics.parse_failures.clone()),
+            Box::new(metrics.rejected_locations.clone()),
+            Box::new(metrics.non_operating_buses.clone()),
+            Box::new(metrics.unmatched_locations.clone()),
+            Box::new(metrics.data_refreshes.clone()),
+            Box::new(metrics.data_version.clone()),
+            Box::new(metrics.last_refresh.clone()),
+            Box::new(metrics.data_age.clone()),
+            Box::new(metrics.process_location_update.clone()),
+        ] {
+            metrics.registry.register(collector).expect("Unique metric");
+        }
+        // Export the known labels from the start, so rates are not missing the first increment.
+        for reason in ["no_position", "no_ride"] {
+            metrics.non_operating_buses.with_label_values(&[reason]);
+        }
+        for reason in ["invalid_coordinates", "backwards", "jump"] {
+            metrics.rejected_locations.with_label_values(&[reason]);
+        }
+        for result in ["success", "failure"] {
+            metrics.data_refreshes.with_label_values(&[result]);
+        }
+        metrics
+    }
+
+    pub fn data_refreshed(&self, version: u64) {
+        self.data_refreshes.with_label_values(&["success"]).inc();
+        self.data_version
+            .set(i64::try_from(version).unwrap_or(i64::MAX));
+        self.last_refresh.set(Utc::now().timestamp());
+    }
+
+    /// Metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        if self.last_refresh.get() > 0 {
+            self.data_age
+                .set(Utc::now().timestamp() - self.last_refresh.get());
+        }
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encodable metrics");
+        String::from_utf8(buffer).expect("UTF-8 metrics")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let sut = Metrics::new();
+        sut.messages_received.with_label_values(&["sub_gps"]).inc();
+        sut.data_refreshed(3);
+        sut.process_location_update.observe(0.001);
+
+        let text = sut.render();
+
+        assert!(text.contains("smart_bus_messages_received_total{event=\"sub_gps\"} 1"));
+        assert!(text.contains("smart_bus_data_refreshes_total{result=\"success\"} 1"));
+        assert!(text.contains("smart_bus_data_version 3"));
+        assert!(text.contains("smart_bus_data_age_seconds"));
+        assert!(text.contains("smart_bus_process_location_update_seconds_count 1"));
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    config::{HeadwayConfig, OffRouteConfig, StopConfig},
+    domain::{Location, Ride, RoutePosition},
+    events::Event,
+    history::{History, HistoryEntry, HistoryWriter},
+    metrics::METRICS,
+    services::{
+        AdherenceService, AssignmentService, BusService, EtaService, FetchService, FilterService,
+        HeadwayService, HeadwayStatus, OffRouteEvent, OffRouteService, RideService, RouteService,
+        StopEvent, StopEventService, Vehicle, VehicleService,
+    },
+};
+
+pub struct Pipeline {
+    bus_lru: Mutex<HashMap<String, NaiveDateTime>>,
+    filter_service: FilterService,
+    assignment_service: AssignmentService,
+    pub ride_service: Arc<RideService>,
+    pub route_service: Arc<RouteService>,
+    pub eta_service: Arc<EtaService>,
+    pub off_route_service: Arc<OffRouteService>,
+    pub adherence_service: Arc<AdherenceService>,
+    pub stop_event_service: Arc<StopEventService>,
+    pub headway_service: Arc<HeadwayService>,
+    pub vehicle_service: Arc<VehicleService>,
+    pub history: Option<Arc<History>>,
+    history_writer: Option<HistoryWriter>,
+}
+
+impl Pipeline {
+    pub fn new(
+        fetch_service: &Arc<FetchService>,
+        off_route: OffRouteConfig,
+        stops: StopConfig,
+        headway: HeadwayConfig,
+    ) -> Self {
+        let bus_service = Arc::new(BusService::new(fetch_service.clone()));
+        let ride_service = Arc::new(RideService::new(fetch_service.clone()));
+        let route_service = Arc::new(RouteService::new(fetch_service.clone()));
+
+        Self {
+            bus_lru: Mutex::new(HashMap::with_capacity(bus_service.number_of_buses())),
+            filter_service: FilterService::new(),
+            assignment_service: AssignmentService::new(
+                bus_service,
+                ride_service.clone(),
+                route_service.clone(),
+            ),
+            ride_service,
+            eta_service: Arc::new(EtaService::new(route_service.clone())),
+            adherence_service: Arc::new(AdherenceService::new(route_service.clone())),
+            stop_event_service: Arc::new(StopEventService::new(stops, route_service.clone())),
+            headway_service: Arc::new(HeadwayService::new(headway, route_service.clone())),
+            route_service,
+            off_route_service: Arc::new(OffRouteService::new(off_route)),
+            vehicle_service: Arc::new(VehicleService::new()),
+            history: None,
+            history_writer: None,
+        }
+    }
+
+    /// Stores every deduplicated location with its matches into the history.
+    pub fn with_history(mut self, history: Arc<History>) -> Self {
+        self.history_writer = Some(HistoryWriter::spawn(history.clone()));
+        self.history = Some(history);
+        self
+    }
+
+    /// Waits for the locations and visits processed so far to be stored into the history.
+    #[cfg(test)]
+    pub fn flush_history(&self) {
+        if let Some(writer) = &self.history_writer {
+            writer.flush();
+        }
+    }
+
+    pub fn process_location_update(&self, value: &str) {
+        let _timer = METRICS.process_location_update.start_timer();
+
+        let location = match serde_json::from_str::<Location>(value) {
+            Ok(value) => value,
+            Err(err) => {
+                METRICS.parse_failures.inc();
+                Event::ParseFailed {
+                    error: format!("{err:#}"),
+                    payload: value.to_string(),
+                }
+                .emit();
+                return;
+            }
+        };
+
+        let last_date_time = self
+            .bus_lru
+            .lock()
+            .unwrap()
+            .insert(location.car_license.clone(), location.date_time);
+        if last_date_time == Some(location.date_time) {
+            // duplicating message, skip
+            METRICS.duplicates_skipped.inc();
+            return;
+        }
+
+        let coordinates = match self.filter_service.filter(&location) {
+            Ok(coordinates) => coordinates,
+            Err(outlier) => {
+                METRICS
+                    .rejected_locations
+                    .with_label_values(&[outlier.reason()])
+                    .inc();
+                Event::LocationRejected {
+                    car_license: location.car_license.clone(),
+                    date_time: location.date_time,
+                    coordinates: location.coordinates,
+                    reason: outlier.to_string(),
+                }
+                .emit();
+                return;
+            }
+        };
+        let mut vehicle = Vehicle::from(&location);
+        vehicle.coordinates = coordinates;
+        // Matched by the smoothed coordinates, the raw ones are kept in the vehicle.
+        let location = Location {
+            coordinates,
+            ..location
+        };
+        self.match_location(&location, &mut vehicle);
+        if let Some(writer) = &self.history_writer {
+            writer.insert(HistoryEntry::from(&vehicle));
+        }
+        self.vehicle_service.update(vehicle.clone());
+        self.track_headway(&vehicle);
+    }
+
+    fn forget(&self, car_license: &str) {
+        self.eta_service.forget(car_license);
+        if let Some(OffRouteEvent::Abandoned { car_license, ride }) =
+            self.off_route_service.forget(car_license)
+        {
+            Event::OffRouteAbandoned {
+                position: ride.name,
+                car_license,
+            }
+            .emit();
+        }
+        self.adherence_service.forget(car_license);
+        self.stop_event_service.forget(car_license);
+        self.headway_service.forget(car_license);
+    }
+
+    fn match_location(&self, location: &Location, vehicle: &mut Vehicle) {
+        let Some(assignment) = self.assignment_service.assign(location) else {
+            self.forget(&location.car_license);
+            METRICS
+                .non_operating_buses
+                .with_label_values(&["no_position"])
+                .inc();
+            Event::NonOperatingBus {
+                car_license: location.car_license.clone(),
+            }
+            .emit();
+            return;
+        };
+        if assignment.inferred && !self.was_inferred(&location.car_license, &assignment.position) {
+            Event::PositionInferred {
+                car_license: location.car_license.clone(),
+                position: assignment.position.clone(),
+            }
+            .emit();
+        }
+        let bus = assignment.position;
+        vehicle.operate_position = Some(bus.clone());
+        vehicle.position_inferred = assignment.inferred;
+
+        let Some(ride) = self.ride_service.get(&bus, location.date_time.time()) else {
+            self.forget(&location.car_license);
+            METRICS
+                .non_operating_buses
+                .with_label_values(&["no_ride"])
+                .inc();
+            Event::NoRideForTime {
+                position: bus,
+                car_license: location.car_license.clone(),
+                time: location.date_time.time(),
+            }
+            .emit();
+            return;
+        };
+        vehicle.ride = Some(ride.clone());
+        vehicle.direction = self.route_service.direction(&ride);
+
+        if let Some(position) = self.route_service.locate(&ride, location.coordinates) {
+            let eta = self
+                .eta_service
+                .update(location, &ride, &position)
+                .and_then(|p| p.arrivals.first().map(|(_, time)| *time));
+
+            Event::PositionMatched {
+                date_time: location.date_time,
+                car_license: location.car_license.clone(),
+                ride: ride.name.clone(),
+                start: ride.start,
+                stop: ride.stop,
+                previous_stop: position.previous.name.clone(),
+                previous_stop_distance: position
+                    .previous
+                    .coordinates
+                    .distance_to(location.coordinates),
+                next_stop: position.next.name.clone(),
+                next_stop_distance: position.next.coordinates.distance_to(location.coordinates),
+                eta,
+                route_distance: position.distance,
+                speed: location.speed,
+                heading: location.heading.0,
+                altitude: location.altitude,
+            }
+            .emit();
+
+            vehicle.off_route = self.track_off_route(location, &ride, bus, position.offset);
+            vehicle.delay = self.track_adherence(location, &ride, &position);
+            self.track_stops(location, &ride, &position);
+
+            vehicle.previous_stop = Some(position.previous.name);
+            vehicle.next_stop = Some(position.next.name);
+            vehicle.route_distance = Some(position.distance);
+        } else {
+            self.forget(&location.car_license);
+            METRICS.unmatched_locations.inc();
+            Event::UnmatchedLocation {
+                ride: ride.name,
+                start: ride.start,
+                stop: ride.stop,
+                car_license: location.car_license.clone(),
+                coordinates: location.coordinates,
+            }
+            .emit();
+        }
+    }
+
+    /// Whether the position has already been inferred for the previous location of the bus.
+    fn was_inferred(&self, car_license: &str, position: &str) -> bool {
+        self.vehicle_service
+            .get(car_license)
+            .is_some_and(|v| v.position_inferred && v.operate_position.as_deref() == Some(position))
+    }
+
+    /// Returns whether the bus is off-route.
+    fn track_off_route(&self, location: &Location, ride: &Ride, bus: String, offset: f64) -> bool {
+        match self.off_route_service.update(location, ride, offset) {
+            Some(OffRouteEvent::OffRoute {
+                car_license,
+                since,
+                deviation,
+                ..
+            }) => Event::OffRoute {
+                position: bus,
+                car_license,
+                since,
+                deviation,
+            }
+            .emit(),
+            Some(OffRouteEvent::BackOnRoute {
+                car_license,
+                deviation,
+                ..
+            }) => Event::BackOnRoute {
+                position: bus,
+                car_license,
+                deviation,
+            }
+            .emit(),
+            Some(OffRouteEvent::Abandoned { .. }) | None => {}
+        }
+        self.off_route_service.is_off_route(&location.car_license)
+    }
+
+    /// Returns the current delay of the bus against the ride schedule.
+    fn track_adherence(
+        &self,
+        location: &Location,
+        ride: &Ride,
+        position: &RoutePosition,
+    ) -> Option<i64> {
+        for adherence in self.adherence_service.update(location, ride, position) {
+            Event::StopPassed {
+                car_license: adherence.car_license,
+                ride: adherence.ride,
+                stop: adherence.stop,
+                passed_at: adherence.passed_at,
+                scheduled_deviation: adherence.scheduled_deviation,
+                advertised_deviation: adherence.advertised_deviation,
+            }
+            .emit();
+        }
+        self.adherence_service
+            .current(&location.car_license)
+            .and_then(|a| a.scheduled_deviation)
+    }
+
+    /// Reports the changes of the headway of the bus behind the bus ahead of it.
+    fn track_headway(&self, vehicle: &Vehicle) {
+        for headway in self
+            .headway_service
+            .update(vehicle, &self.vehicle_service.all())
+        {
+            match headway.status {
+                HeadwayStatus::Bunching => Event::Bunching {
+                    direction: headway.direction,
+                    car_license: headway.car_license,
+                    leader: headway.leader,
+                    distance: headway.distance,
+                    estimated_headway: headway.estimated_time,
+                    scheduled: headway.scheduled,
+                },
+                HeadwayStatus::Gap => Event::HeadwayGap {
+                    direction: headway.direction,
+                    car_license: headway.car_license,
+                    leader: headway.leader,
+                    distance: headway.distance,
+                    estimated_headway: headway.estimated_time,
+                    scheduled: headway.scheduled,
+                },
+                HeadwayStatus::Regular => Event::HeadwayRestored {
+                    direction: headway.direction,
+                    car_license: headway.car_license,
+                    leader: headway.leader,
+                    estimated_headway: headway.estimated_time,
+                },
+            }
+            .emit();
+        }
+    }
+
+    /// Reports the transitions between the stops, storing the visits into the history.
+    fn track_stops(&self, location: &Location, ride: &Ride, position: &RoutePosition) {
+        let car_license = location.car_license.clone();
+        for event in self.stop_event_service.update(location, ride, position) {
+            match event {
+                StopEvent::Approaching { stop, distance } => Event::ApproachingStop {
+                    car_license: car_license.clone(),
+                    ride: ride.name.clone(),
+                    stop: stop.name,
+                    date_time: location.date_time,
+                    distance,
+                },
+                StopEvent::Arrived { stop } => Event::ArrivedAtStop {
+                    car_license: car_license.clone(),
+                    ride: ride.name.clone(),
+                    stop: stop.name,
+                    date_time: location.date_time,
+                },
+                StopEvent::Departed { visit } => {
+                    if let Some(writer) = &self.history_writer {
+                        writer.insert_visit(visit.clone());
+                    }
+                    Event::DepartedStop {
+                        dwell: visit.dwell(),
+                        car_license: visit.car_license,
+                        ride: visit.ride,
+                        stop: visit.stop,
+                        date_time: visit.departed_at,
+                        arrived_at: visit.arrived_at,
+                    }
+                }
+                StopEvent::Passed { stop } => Event::PassedStopWithoutStopping {
+                    car_license: car_license.clone(),
+                    ride: ride.name.clone(),
+                    stop: stop.name,
+                    date_time: location.date_time,
+                },
+            }
+            .emit();
+        }
+    }
+}
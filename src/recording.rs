@@ -0,0 +1,188 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, ensure};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Slower replays would wait for hours between payloads.
+const MIN_REPLAY_FACTOR: f64 = 0.01;
+/// Faster replays are as fast as `max`.
+const MAX_REPLAY_FACTOR: f64 = 1e6;
+
+/// A single raw `sub_gps` payload as it was received from the socket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub received_at: DateTime<Utc>,
+    pub payload: String,
+}
+
+/// Appends every received payload to a JSONL file, one [`Record`] per line.
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    pub fn record(&self, payload: &str) -> anyhow::Result<()> {
+        let record = Record {
+            received_at: Utc::now(),
+            payload: payload.to_string(),
+        };
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&line)?;
+        // Flush every line, so the recording survives an abrupt stop.
+        writer.flush().map_err(Into::into)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the original gaps between payloads.
+    Real,
+    /// Divide the original gaps by the factor.
+    Accelerated(f64),
+    /// Do not wait between payloads at all.
+    Max,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "real" => Ok(Self::Real),
+            "max" => Ok(Self::Max),
+            _ => {
+                let factor = s
+                    .strip_suffix('x')
+                    .unwrap_or(s)
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("unknown replay speed: {s}"))?;
+                ensure!(
+                    (MIN_REPLAY_FACTOR..=MAX_REPLAY_FACTOR).contains(&factor),
+                    "replay speed must be from {MIN_REPLAY_FACTOR}x to {MAX_REPLAY_FACTOR}x, got {factor}"
+                );
+                Ok(Self::Accelerated(factor))
+            }
+        }
+    }
+}
+
+impl ReplaySpeed {
+    fn delay(self, gap: chrono::TimeDelta) -> Option<std::time::Duration> {
+        let gap = gap.to_std().ok()?;
+        match self {
+            Self::Real => Some(gap),
+            Self::Accelerated(factor) => Some(gap.div_f64(factor)),
+            Self::Max => None,
+        }
+    }
+}
+
+pub fn read_records<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Record>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?).map_err(|err| anyhow!("line {}: {err:#}", index + 1))
+        })
+        .collect()
+}
+
+/// Feeds recorded payloads to `process` in their original order, pacing them by `speed`.
+/// Returns the number of replayed payloads.
+pub async fn replay<F>(records: &[Record], speed: ReplaySpeed, mut process: F) -> usize
+where
+    F: FnMut(&str),
+{
+    let mut previous: Option<DateTime<Utc>> = None;
+
+    for record in records {
+        if let Some(delay) =
+            previous.and_then(|previous| speed.delay(record.received_at - previous))
+        {
+            tokio::time::sleep(delay).await;
+        }
+        previous = Some(record.received_at);
+        process(&record.payload);
+    }
+
+    records.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::real("real", ReplaySpeed::Real)]
+    #[case::max("max", ReplaySpeed::Max)]
+    #[case::factor("10", ReplaySpeed::Accelerated(10.0))]
+    #[case::factor_x("2.5x", ReplaySpeed::Accelerated(2.5))]
+    fn parse_speed(#[case] input: &str, #[case] expected: ReplaySpeed) {
+        assert_eq!(expected, input.parse().unwrap());
+    }
+
+    #[rstest]
+    #[case::zero("0")]
+    #[case::negative("-1x")]
+    #[case::garbage("fast")]
+    #[case::tiny("0.0000001x")]
+    #[case::infinite("inf")]
+    #[case::nan("NaN")]
+    fn parse_invalid_speed(#[case] input: &str) {
+        assert!(input.parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn record_and_read() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", std::process::id()));
+
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.record(r#"{"carlicense":"10-1155"}"#).unwrap();
+        recorder.record(r#"{"carlicense":"10-1152"}"#).unwrap();
+        drop(recorder);
+
+        let records = read_records(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, records.len());
+        assert_eq!(r#"{"carlicense":"10-1155"}"#, records[0].payload);
+        assert!(records[0].received_at <= records[1].received_at);
+    }
+
+    #[tokio::test]
+    async fn replay_in_order() {
+        let start = Utc::now();
+        let records = (0..3)
+            .map(|i| Record {
+                received_at: start + chrono::TimeDelta::try_seconds(i * 60).unwrap(),
+                payload: i.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut replayed = vec![];
+        let count = replay(&records, ReplaySpeed::Max, |p| replayed.push(p.to_string())).await;
+
+        assert_eq!(3, count);
+        assert_eq!(vec!["0", "1", "2"], replayed);
+    }
+}
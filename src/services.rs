@@ -1,9 +1,25 @@
+mod adherence_service;
+mod assignment_service;
 mod bus_service;
+mod eta_service;
 mod fetch_service;
+mod filter_service;
+mod headway_service;
+mod off_route_service;
 mod ride_service;
 mod route_service;
+mod stop_event_service;
+mod vehicle_service;
 
+pub use adherence_service::{Adherence, AdherenceService};
+pub use assignment_service::AssignmentService;
 pub use bus_service::BusService;
-pub use fetch_service::FetchService;
+pub use eta_service::EtaService;
+pub use fetch_service::{run_refresh, FetchService, Snapshot};
+pub use filter_service::FilterService;
+pub use headway_service::{Headway, HeadwayService, HeadwayStatus};
+pub use off_route_service::{OffRouteEvent, OffRouteService};
 pub use ride_service::RideService;
 pub use route_service::RouteService;
+pub use stop_event_service::{StopEvent, StopEventService, StopVisit};
+pub use vehicle_service::{Vehicle, VehicleService};
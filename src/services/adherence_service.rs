@@ -0,0 +1,502 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{NaiveDateTime, NaiveTime};
+use serde::Serialize;
+
+use crate::domain::{Location, Ride, RouteDirection, RoutePosition, Stop};
+
+use super::RouteService;
+
+type CarLicense = String;
+
+/// How many passings are kept per stop.
+const STOP_HISTORY_LEN: usize = 50;
+
+pub struct AdherenceService {
+    route_service: Arc<RouteService>,
+    inner: RwLock<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    last: HashMap<CarLicense, LastPosition>,
+    current: HashMap<CarLicense, Adherence>,
+    stops: HashMap<(RouteDirection, String), VecDeque<Adherence>>,
+}
+
+/// The furthest location of the bus along the route of its ride.
+#[derive(Debug, Clone)]
+struct LastPosition {
+    ride: Ride,
+    date_time: NaiveDateTime,
+    distance: f64,
+    /// Stops of the ride passed so far, counted from its start.
+    passed: usize,
+}
+
+/// Schedule adherence of the bus passing a stop.
+/// Deviations are in seconds, positive when the bus is late.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Adherence {
+    pub car_license: CarLicense,
+    pub ride: String,
+    pub direction: RouteDirection,
+    pub stop: String,
+    pub passed_at: NaiveDateTime,
+    /// The closest time advertised at the stop.
+    pub advertised: Option<NaiveTime>,
+    pub advertised_deviation: Option<i64>,
+    /// The time interpolated from the ride departure and arrival.
+    pub scheduled: Option<NaiveTime>,
+    pub scheduled_deviation: Option<i64>,
+}
+
+impl AdherenceService {
+    pub fn new(route_service: Arc<RouteService>) -> Self {
+        Self {
+            route_service,
+            inner: RwLock::default(),
+        }
+    }
+
+    /// The adherence at the last stop passed by the bus.
+    pub fn current(&self, car_license: &str) -> Option<Adherence> {
+        self.inner.read().unwrap().current.get(car_license).cloned()
+    }
+
+    /// Current adherence of all buses, the latest first.
+    pub fn all(&self) -> Vec<Adherence> {
+        let mut all = self
+            .inner
+            .read()
+            .unwrap()
+            .current
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        all.sort_by_key(|a| std::cmp::Reverse(a.scheduled_deviation));
+        all
+    }
+
+    /// Recent passings of the stop, the oldest first.
+    pub fn history(&self, direction: RouteDirection, stop: &str) -> Vec<Adherence> {
+        self.inner
+            .read()
+            .unwrap()
+            .stops
+            .get(&(direction, stop.to_string()))
+            .map(|h| h.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the adherence at every stop the bus has passed since its previous location,
+    /// in travel order.
+    pub fn update(
+        &self,
+        location: &Location,
+        ride: &Ride,
+        position: &RoutePosition,
+    ) -> Vec<Adherence> {
+        let timetable = self.route_service.timetable(ride);
+        let mut distances = Vec::with_capacity(timetable.len());
+        let mut distance = 0.0;
+        for (index, (stop, _)) in timetable.iter().enumerate() {
+            if index > 0 {
+                distance += timetable[index - 1]
+                    .0
+                    .coordinates
+                    .distance_to(stop.coordinates);
+            }
+            distances.push(distance);
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        let last = match inner.last.get(&location.car_license) {
+            Some(last) if last.ride == *ride => last.clone(),
+            previous => {
+                if previous.is_some() {
+                    inner.current.remove(&location.car_license);
+                }
+                // The stops behind the bus were passed before it was seen on the ride.
+                let passed = distances.iter().filter(|d| **d < position.distance).count();
+                inner.last.insert(
+                    location.car_license.clone(),
+                    LastPosition::new(ride, location, position, passed),
+                );
+                return vec![];
+            }
+        };
+        // Jitter backwards, the bus is still as far as it has been.
+        if position.distance < last.distance {
+            return vec![];
+        }
+        // The bus has passed the stops between the locations, several when some were missed.
+        let passed = (last.passed..distances.len())
+            .take_while(|index| distances[*index] < position.distance)
+            .collect::<Vec<_>>();
+        inner.last.insert(
+            location.car_license.clone(),
+            LastPosition::new(ride, location, position, last.passed + passed.len()),
+        );
+        drop(inner);
+
+        let Some(direction) = self.route_service.direction(ride) else {
+            return vec![];
+        };
+        let adherences = passed
+            .into_iter()
+            .map(|index| {
+                let (stop, scheduled) = &timetable[index];
+                let passed_at = interpolate(
+                    &last,
+                    location.date_time,
+                    position.distance,
+                    distances[index],
+                );
+                adherence(location, ride, direction, stop, *scheduled, passed_at)
+            })
+            .collect::<Vec<_>>();
+
+        let mut inner = self.inner.write().unwrap();
+        for adherence in &adherences {
+            inner
+                .current
+                .insert(location.car_license.clone(), adherence.clone());
+            let history = inner
+                .stops
+                .entry((adherence.direction, adherence.stop.clone()))
+                .or_default();
+            if history.len() == STOP_HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(adherence.clone());
+        }
+        drop(inner);
+
+        adherences
+    }
+
+    pub fn forget(&self, car_license: &str) {
+        let mut inner = self.inner.write().unwrap();
+        inner.last.remove(car_license);
+        inner.current.remove(car_license);
+    }
+}
+
+impl LastPosition {
+    fn new(ride: &Ride, location: &Location, position: &RoutePosition, passed: usize) -> Self {
+        Self {
+            ride: ride.clone(),
+            date_time: location.date_time,
+            distance: position.distance,
+            passed,
+        }
+    }
+}
+
+/// The adherence of the bus passing the stop at `passed_at`.
+fn adherence(
+    location: &Location,
+    ride: &Ride,
+    direction: RouteDirection,
+    stop: &Stop,
+    scheduled: NaiveTime,
+    passed_at: NaiveDateTime,
+) -> Adherence {
+    let advertised = stop
+        .schedule
+        .iter()
+        .min_by_key(|t| (passed_at.time() - **t).num_seconds().abs())
+        .copied();
+    let deviation = |t: NaiveTime| (passed_at.time() - t).num_seconds();
+
+    Adherence {
+        car_license: location.car_license.clone(),
+        ride: ride.name.clone(),
+        direction,
+        stop: stop.name.clone(),
+        passed_at,
+        advertised,
+        advertised_deviation: advertised.map(deviation),
+        scheduled: Some(scheduled),
+        scheduled_deviation: Some(deviation(scheduled)),
+    }
+}
+
+/// Time when the bus was at `stop_distance` along the route, assuming constant speed
+/// since the last location.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn interpolate(
+    last: &LastPosition,
+    date_time: NaiveDateTime,
+    distance: f64,
+    stop_distance: f64,
+) -> NaiveDateTime {
+    let fraction = ((stop_distance - last.distance) / (distance - last.distance)).clamp(0.0, 1.0);
+    let elapsed = (date_time - last.date_time).num_milliseconds() as f64;
+    last.date_time + chrono::TimeDelta::milliseconds((elapsed * fraction).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeDelta};
+
+    use crate::{
+        domain::{Coordinates, Latitude, Longitude, Stop, Terminal},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    fn ride() -> Ride {
+        Ride {
+            name: "Bus7".to_string(),
+            start: Terminal::Airport,
+            stop: Terminal::Rawai,
+            loading: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        }
+    }
+
+    fn location(coordinates: Coordinates, time: NaiveTime) -> Location {
+        serde_json::from_str(
+            &serde_json::json!({
+            "deviceno": "0088007439",
+            "lat": coordinates.latitude.0.to_string(),
+            "lng": coordinates.longitude.0.to_string(),
+            "state": 1,
+            "speed": 40,
+            "direction": 180.0,
+            "altitude": 10,
+            "dateTime": NaiveDate::from_ymd_opt(2024, 3, 20)
+                .unwrap()
+                .and_time(time)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            "vid": 1,
+            "carlicense": "10-1152",
+            "groupName": "Phuket Smart Bus"
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    fn between(a: &Stop, b: &Stop) -> Coordinates {
+        Coordinates::new(
+            Longitude(f32::midpoint(
+                a.coordinates.longitude.0,
+                b.coordinates.longitude.0,
+            )),
+            Latitude(f32::midpoint(
+                a.coordinates.latitude.0,
+                b.coordinates.latitude.0,
+            )),
+        )
+    }
+
+    fn sut() -> (AdherenceService, Arc<RouteService>, Vec<Stop>) {
+        let route_service = Arc::new(RouteService::new(Arc::new(FetchService::for_tests())));
+        let stops = route_service.ride_stops(&ride());
+        (
+            AdherenceService::new(route_service.clone()),
+            route_service,
+            stops,
+        )
+    }
+
+    fn update(
+        sut: &AdherenceService,
+        route_service: &RouteService,
+        coordinates: Coordinates,
+        time: NaiveTime,
+    ) -> Vec<Adherence> {
+        let position = route_service.locate(&ride(), coordinates).unwrap();
+        sut.update(&location(coordinates, time), &ride(), &position)
+    }
+
+    #[test]
+    fn passing_a_stop() {
+        let (sut, route_service, stops) = sut();
+        let scheduled = route_service.timetable(&ride())[1].1;
+
+        let before = between(&stops[0], &stops[1]);
+        let after = between(&stops[1], &stops[2]);
+        assert!(update(
+            &sut,
+            &route_service,
+            before,
+            scheduled - TimeDelta::minutes(5)
+        )
+        .is_empty());
+        assert!(update(
+            &sut,
+            &route_service,
+            before,
+            scheduled - TimeDelta::minutes(4)
+        )
+        .is_empty());
+
+        let mut passed = update(
+            &sut,
+            &route_service,
+            after,
+            scheduled + TimeDelta::minutes(6),
+        );
+        assert_eq!(1, passed.len());
+        let adherence = passed.pop().unwrap();
+
+        assert_eq!(stops[1].name, adherence.stop);
+        assert!(adherence.passed_at.time() > scheduled - TimeDelta::minutes(4));
+        assert!(adherence.passed_at.time() < scheduled + TimeDelta::minutes(6));
+        assert_eq!(
+            Some((adherence.passed_at.time() - scheduled).num_seconds()),
+            adherence.scheduled_deviation
+        );
+        assert!(stops[1].schedule.contains(&adherence.advertised.unwrap()));
+
+        assert_eq!(Some(adherence.clone()), sut.current("10-1152"));
+        assert_eq!(
+            vec![adherence],
+            sut.history(RouteDirection(Terminal::Rawai), &stops[1].name)
+        );
+        assert!(sut
+            .history(RouteDirection(Terminal::Airport), &stops[1].name)
+            .is_empty());
+    }
+
+    #[test]
+    fn advertised_deviation() {
+        let (sut, route_service, stops) = sut();
+        // Thalang Public Health Office is advertised at 15:01 and 15:04.
+        let before = between(&stops[0], &stops[1]);
+        let after = between(&stops[1], &stops[2]);
+
+        update(
+            &sut,
+            &route_service,
+            before,
+            NaiveTime::from_hms_opt(15, 10, 0).unwrap(),
+        );
+        let adherence = update(
+            &sut,
+            &route_service,
+            after,
+            NaiveTime::from_hms_opt(15, 20, 0).unwrap(),
+        )
+        .pop()
+        .unwrap();
+
+        assert_eq!(NaiveTime::from_hms_opt(15, 4, 0), adherence.advertised);
+        assert_eq!("Thalang Public Health Office", adherence.stop);
+        assert_eq!(
+            Some((adherence.passed_at.time() - adherence.advertised.unwrap()).num_seconds()),
+            adherence.advertised_deviation
+        );
+        assert!(adherence.advertised_deviation.unwrap() > 0);
+    }
+
+    #[test]
+    fn skipping_stops() {
+        let (sut, route_service, stops) = sut();
+        let timetable = route_service.timetable(&ride());
+        // Halfway between the stop and the next one on time.
+        let on_time = |i: usize| timetable[i].1 + (timetable[i + 1].1 - timetable[i].1) / 2;
+
+        update(
+            &sut,
+            &route_service,
+            between(&stops[0], &stops[1]),
+            on_time(0),
+        );
+        let passed = update(
+            &sut,
+            &route_service,
+            between(&stops[3], &stops[4]),
+            on_time(3),
+        );
+
+        assert_eq!(
+            vec![&stops[1].name, &stops[2].name, &stops[3].name],
+            passed.iter().map(|a| &a.stop).collect::<Vec<_>>()
+        );
+        assert!(passed.windows(2).all(|w| w[0].passed_at < w[1].passed_at));
+        // Driving on time, every stop is passed close to its scheduled time.
+        assert!(passed
+            .iter()
+            .all(|a| a.scheduled_deviation.unwrap().abs() < 120));
+        assert_eq!(passed.last().cloned(), sut.current("10-1152"));
+        assert_eq!(
+            vec![passed[1].clone()],
+            sut.history(RouteDirection(Terminal::Rawai), &stops[2].name)
+        );
+    }
+
+    #[test]
+    fn jitter_back_across_a_stop() {
+        let (sut, route_service, stops) = sut();
+        let scheduled = route_service.timetable(&ride())[1].1;
+        let before = between(&stops[0], &stops[1]);
+        let after = between(&stops[1], &stops[2]);
+
+        update(
+            &sut,
+            &route_service,
+            before,
+            scheduled - TimeDelta::minutes(2),
+        );
+        let passed = update(&sut, &route_service, after, scheduled);
+        assert!(update(
+            &sut,
+            &route_service,
+            before,
+            scheduled + TimeDelta::minutes(1)
+        )
+        .is_empty());
+        assert!(update(
+            &sut,
+            &route_service,
+            after,
+            scheduled + TimeDelta::minutes(2)
+        )
+        .is_empty());
+
+        assert_eq!(1, passed.len());
+        assert_eq!(
+            passed,
+            sut.history(RouteDirection(Terminal::Rawai), &stops[1].name)
+        );
+    }
+
+    #[test]
+    fn departing_the_terminal() {
+        let (sut, route_service, stops) = sut();
+        let departure = ride().departure;
+
+        update(
+            &sut,
+            &route_service,
+            stops[0].coordinates,
+            departure - TimeDelta::minutes(5),
+        );
+        update(&sut, &route_service, stops[0].coordinates, departure);
+        let passed = update(
+            &sut,
+            &route_service,
+            between(&stops[0], &stops[1]),
+            departure + TimeDelta::minutes(1),
+        );
+
+        assert_eq!(
+            vec![(stops[0].name.clone(), Some(0))],
+            passed
+                .into_iter()
+                .map(|a| (a.stop, a.scheduled_deviation))
+                .collect::<Vec<_>>()
+        );
+    }
+}
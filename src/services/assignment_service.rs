@@ -0,0 +1,371 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::Serialize;
+
+use crate::domain::{Coordinates, Location};
+
+use super::{BusService, RideService, RouteService};
+
+type CarLicense = String;
+
+/// Locations of a bus scored against the positions, the latest ones.
+const TRACK_LEN: usize = 5;
+/// Older locations are dropped from the track.
+const TRACK_AGE: TimeDelta = TimeDelta::minutes(15);
+/// Fewer locations tell nothing about the position.
+const MIN_TRACK_LEN: usize = 3;
+/// Distance from the route of the ride within which the track matches it, meters.
+const ON_ROUTE_M: f64 = 150.0;
+/// The track should move forward along the route of the ride at least this far, meters.
+const MIN_PROGRESS_M: f64 = 100.0;
+/// Jitter of a standing bus projected onto the route may move it back this far, meters.
+const BACKWARD_TOLERANCE_M: f64 = 10.0;
+/// An inferred position is kept unless another one runs closer to its timetable by this much.
+const SWITCH_MARGIN: TimeDelta = TimeDelta::minutes(2);
+/// How far the bus may run from the timetable of the ride and still match it.
+const MAX_DEVIATION: TimeDelta = TimeDelta::minutes(20);
+
+/// Assigns buses to the schedule positions, inferring the position from the track of the bus
+/// when the sheet has none or one the bus clearly does not drive.
+pub struct AssignmentService {
+    bus_service: Arc<BusService>,
+    ride_service: Arc<RideService>,
+    route_service: Arc<RouteService>,
+    tracks: RwLock<HashMap<CarLicense, VecDeque<(NaiveDateTime, Coordinates)>>>,
+    /// Positions the tracks of the buses have matched, with the time of the match.
+    confirmed: RwLock<HashMap<CarLicense, (String, NaiveDateTime)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Assignment {
+    pub position: String,
+    /// Not from the sheet, but from the track of the bus.
+    pub inferred: bool,
+}
+
+impl AssignmentService {
+    pub fn new(
+        bus_service: Arc<BusService>,
+        ride_service: Arc<RideService>,
+        route_service: Arc<RouteService>,
+    ) -> Self {
+        Self {
+            bus_service,
+            ride_service,
+            route_service,
+            tracks: RwLock::default(),
+            confirmed: RwLock::default(),
+        }
+    }
+
+    /// Tracks the bus and returns its position: the one of the sheet unless the track matches
+    /// no ride of it, otherwise the position whose current ride the track matches best.
+    pub fn assign(&self, location: &Location) -> Option<Assignment> {
+        let track = self.track(location);
+        let sheet = self.bus_service.operate_position(&location.car_license);
+        if track.len() < MIN_TRACK_LEN {
+            return sheet.map(|position| Assignment {
+                position,
+                inferred: false,
+            });
+        }
+
+        if let Some(position) = &sheet {
+            if self.deviation(&track, position).is_some() {
+                self.confirm(location, position);
+                return sheet.map(|position| Assignment {
+                    position,
+                    inferred: false,
+                });
+            }
+        }
+
+        let taken = self.taken(location);
+        let candidates = self
+            .ride_service
+            .positions()
+            .into_iter()
+            .filter(|p| !taken.contains(p))
+            .filter_map(|p| Some((self.deviation(&track, &p)?, p)))
+            .collect::<Vec<_>>();
+        let kept = self.confirmed(location).and_then(|previous| {
+            candidates
+                .iter()
+                .find(|(_, p)| *p == previous)
+                .map(|(deviation, _)| (*deviation, previous))
+        });
+        let inferred = match (candidates.iter().min(), kept) {
+            (Some((best, _)), Some((deviation, previous)))
+                if deviation - best < SWITCH_MARGIN.num_seconds() =>
+            {
+                Some(previous)
+            }
+            (best, _) => best.map(|(_, position)| position.clone()),
+        };
+        if let Some(position) = inferred {
+            self.confirm(location, &position);
+            return Some(Assignment {
+                position,
+                inferred: true,
+            });
+        }
+        sheet.map(|position| Assignment {
+            position,
+            inferred: false,
+        })
+    }
+
+    fn track(&self, location: &Location) -> Vec<(NaiveDateTime, Coordinates)> {
+        let mut tracks = self.tracks.write().unwrap();
+        let track = tracks.entry(location.car_license.clone()).or_default();
+        track.push_back((location.date_time, location.coordinates));
+        while track.len() > TRACK_LEN
+            || track
+                .front()
+                .is_some_and(|(t, _)| location.date_time - *t > TRACK_AGE)
+        {
+            track.pop_front();
+        }
+        let track = track.iter().copied().collect();
+        drop(tracks);
+        track
+    }
+
+    fn confirm(&self, location: &Location, position: &str) {
+        self.confirmed.write().unwrap().insert(
+            location.car_license.clone(),
+            (position.to_string(), location.date_time),
+        );
+    }
+
+    /// The position the bus has recently been confirmed on.
+    fn confirmed(&self, location: &Location) -> Option<String> {
+        self.confirmed
+            .read()
+            .unwrap()
+            .get(&location.car_license)
+            .filter(|(_, at)| location.date_time - *at <= TRACK_AGE)
+            .map(|(position, _)| position.clone())
+    }
+
+    /// Positions of the other buses recently seen, the ones their tracks have recently been
+    /// confirmed on, otherwise the ones of the sheet.
+    fn taken(&self, location: &Location) -> Vec<String> {
+        let confirmed = self.confirmed.read().unwrap();
+        let taken = self
+            .tracks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(license, _)| **license != location.car_license)
+            .filter(|(_, track)| {
+                track
+                    .back()
+                    .is_some_and(|(at, _)| location.date_time - *at <= TRACK_AGE)
+            })
+            .filter_map(|(license, _)| {
+                confirmed
+                    .get(license)
+                    .filter(|(_, at)| location.date_time - *at <= TRACK_AGE)
+                    .map(|(position, _)| position.clone())
+                    .or_else(|| self.bus_service.operate_position(license))
+            })
+            .collect();
+        drop(confirmed);
+        taken
+    }
+
+    /// How far the latest location runs from the timetable of the current ride of the
+    /// position, if the whole track moves forward along the route of the ride.
+    fn deviation(&self, track: &[(NaiveDateTime, Coordinates)], position: &str) -> Option<i64> {
+        let (now, coordinates) = *track.last()?;
+        let ride = self.ride_service.get(position, now.time())?;
+
+        let mut distances = Vec::with_capacity(track.len());
+        for (_, c) in track {
+            let located = self.route_service.locate(&ride, *c)?;
+            if located.offset > ON_ROUTE_M {
+                return None;
+            }
+            distances.push(located.distance);
+        }
+        if distances
+            .windows(2)
+            .any(|w| w[1] < w[0] - BACKWARD_TOLERANCE_M)
+            || distances.last()? - distances.first()? < MIN_PROGRESS_M
+        {
+            return None;
+        }
+
+        let position = self.route_service.locate(&ride, coordinates)?;
+        let timetable = self.route_service.timetable(&ride);
+        let index = timetable
+            .iter()
+            .position(|(s, _)| s.name == position.previous.name)?;
+        let (previous, next) = (timetable[index].1, timetable.get(index + 1)?.1);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        let expected = previous
+            + TimeDelta::seconds(
+                ((next - previous).num_seconds() as f64 * position.fraction) as i64,
+            );
+
+        let deviation = (now.time() - expected).abs();
+        (deviation <= MAX_DEVIATION).then(|| deviation.num_seconds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::{
+        domain::{Latitude, Longitude, Ride},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    fn sut() -> AssignmentService {
+        let fetch_service = Arc::new(FetchService::for_tests());
+        AssignmentService::new(
+            Arc::new(BusService::new(fetch_service.clone())),
+            Arc::new(RideService::new(fetch_service.clone())),
+            Arc::new(RouteService::new(fetch_service)),
+        )
+    }
+
+    fn location(car_license: &str, coordinates: Coordinates, time: NaiveTime) -> Location {
+        let date_time = NaiveDate::from_ymd_opt(2024, 3, 20).unwrap().and_time(time);
+        serde_json::from_str(&format!(
+            r#"{{"deviceno":"0088007439","lat":"{}","lng":"{}","state":1,"speed":38,"direction":160.2,"altitude":12,"dateTime":"{}","vid":246,"carlicense":"{car_license}","groupName":"Phuket Smart Bus"}}"#,
+            coordinates.latitude.0,
+            coordinates.longitude.0,
+            date_time.format("%Y-%m-%d %H:%M:%S")
+        ))
+        .unwrap()
+    }
+
+    /// Assigns the bus driving from the third to the fourth stop of the ride on its timetable.
+    fn drive(sut: &AssignmentService, car_license: &str, ride: &Ride) -> Vec<Option<Assignment>> {
+        let timetable = sut.route_service.timetable(ride);
+        let ((a, from), (b, to)) = (&timetable[2], &timetable[3]);
+        (0..4_i16)
+            .map(|step| {
+                let fraction = f32::from(step) / 3.0;
+                let coordinates = Coordinates::new(
+                    Longitude(
+                        (b.coordinates.longitude.0 - a.coordinates.longitude.0)
+                            .mul_add(fraction, a.coordinates.longitude.0),
+                    ),
+                    Latitude(
+                        (b.coordinates.latitude.0 - a.coordinates.latitude.0)
+                            .mul_add(fraction, a.coordinates.latitude.0),
+                    ),
+                );
+                let time = *from + (*to - *from) * i32::from(step) / 3;
+                sut.assign(&location(car_license, coordinates, time))
+            })
+            .collect()
+    }
+
+    fn ride(position: &str, hour: u32, minute: u32) -> Ride {
+        RideService::new(Arc::new(FetchService::for_tests()))
+            .get(position, NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+            .unwrap()
+    }
+
+    fn assignment(position: &str, inferred: bool) -> Assignment {
+        Assignment {
+            position: position.to_string(),
+            inferred,
+        }
+    }
+
+    #[test]
+    fn infer_missing_position() {
+        let sut = sut();
+
+        // 10-1147 has no position in the sheet.
+        let assignments = drive(&sut, "10-1147", &ride("Bus8", 15, 40));
+
+        assert_eq!(None, assignments[1]);
+        assert_eq!(Some(assignment("Bus8", true)), assignments[3]);
+    }
+
+    #[test]
+    fn keep_sheet_position() {
+        let sut = sut();
+
+        let assignments = drive(&sut, "10-1152", &ride("Bus7", 15, 10));
+
+        assert!(assignments
+            .iter()
+            .all(|a| *a == Some(assignment("Bus7", false))));
+    }
+
+    #[test]
+    fn replace_suspicious_position() {
+        let sut = sut();
+
+        // Bus7 of the sheet has departed half an hour earlier.
+        let assignments = drive(&sut, "10-1152", &ride("Bus8", 15, 40));
+
+        assert_eq!(Some(assignment("Bus7", false)), assignments[1]);
+        assert_eq!(Some(assignment("Bus8", true)), assignments[3]);
+    }
+
+    #[test]
+    fn keep_inferred_position_standing() {
+        let sut = sut();
+        let ride = ride("Bus8", 15, 40);
+        drive(&sut, "10-1147", &ride);
+
+        // Standing at the fourth stop, the projection jittering back and forth.
+        let (stop, arrival) = &sut.route_service.timetable(&ride)[3];
+        let jitter = |meters: f32, seconds: i64| {
+            let coordinates = Coordinates::new(
+                stop.coordinates.longitude,
+                Latitude(stop.coordinates.latitude.0 + meters / 111_320.0),
+            );
+            sut.assign(&location(
+                "10-1147",
+                coordinates,
+                *arrival + TimeDelta::seconds(seconds),
+            ))
+        };
+
+        assert_eq!(Some(assignment("Bus8", true)), jitter(3.0, 30));
+        assert_eq!(Some(assignment("Bus8", true)), jitter(-3.0, 60));
+    }
+
+    #[test]
+    fn sheet_position_of_standing_bus() {
+        let sut = sut();
+        let ride = ride("Bus8", 15, 40);
+        let timetable = sut.route_service.timetable(&ride);
+        // 10-1155 is on Bus8 in the sheet, seen without a track to confirm it.
+        sut.assign(&location(
+            "10-1155",
+            timetable[0].0.coordinates,
+            timetable[2].1,
+        ));
+
+        let assignments = drive(&sut, "10-1147", &ride);
+
+        assert_eq!(None, assignments[3]);
+    }
+
+    #[test]
+    fn position_taken_by_another_bus() {
+        let sut = sut();
+        drive(&sut, "10-1155", &ride("Bus8", 15, 40));
+
+        let assignments = drive(&sut, "10-1147", &ride("Bus8", 15, 40));
+
+        assert_eq!(None, assignments[3]);
+    }
+}
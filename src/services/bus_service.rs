@@ -6,7 +6,7 @@ use std::{
     },
 };
 
-use crate::domain::Bus;
+use crate::{domain::Bus, events::Event};
 
 use super::FetchService;
 
@@ -28,13 +28,15 @@ impl BusService {
         }
     }
 
+    /// The position of the bus in the sheet, none if the cell is blank.
     pub fn operate_position(&self, car_license: &str) -> Option<String> {
         self.update_if_neeeded();
         self.buses
             .read()
             .unwrap()
             .get(car_license)
-            .map(|b| b.operate_position.clone())
+            .map(|b| b.operate_position.trim().to_string())
+            .filter(|p| !p.is_empty())
     }
 
     pub fn number_of_buses(&self) -> usize {
@@ -42,29 +44,30 @@ impl BusService {
     }
 
     fn update_if_neeeded(&self) {
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        let snapshot = self.fetch_service.snapshot();
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
-        let buses = self
-            .fetch_service
-            .buses()
-            .into_iter()
-            .map(|bus| (bus.licence_plate_no.clone(), bus))
+        let buses = snapshot
+            .buses
+            .iter()
+            .map(|bus| (bus.licence_plate_no.clone(), bus.clone()))
             .collect();
 
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
         *self.buses.write().unwrap() = buses;
         self.current_version
-            .store(self.fetch_service.version(), Ordering::Relaxed);
+            .store(snapshot.version, Ordering::Relaxed);
 
-        println!(
-            "Buses updated, version {}",
-            self.current_version.load(Ordering::Acquire)
-        );
+        Event::DataRefreshed {
+            source: "Buses".to_string(),
+            version: self.current_version.load(Ordering::Acquire),
+        }
+        .emit();
     }
 }
 
@@ -76,5 +79,6 @@ mod tests {
     fn operate_position() {
         let sut = BusService::new(Arc::new(FetchService::for_tests()));
         assert_eq!(sut.operate_position("10-1152").as_deref(), Some("Bus7"));
+        assert_eq!(sut.operate_position("10-1147"), None);
     }
 }
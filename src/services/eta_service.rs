@@ -0,0 +1,288 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{NaiveDateTime, TimeDelta};
+
+use crate::domain::{Location, Ride, RouteDirection, RoutePosition, Stop};
+
+use super::RouteService;
+
+type CarLicense = String;
+
+/// Below this speed the bus is considered standing, e.g. at a stop or in a jam,
+/// and only the scheduled speed is used.
+const STANDING_SPEED_KMH: u32 = 5;
+/// How quickly the current speed loses its weight with the distance ahead.
+const CURRENT_SPEED_HORIZON_M: f64 = 2_000.0;
+
+pub struct EtaService {
+    route_service: Arc<RouteService>,
+    predictions: RwLock<HashMap<CarLicense, Prediction>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prediction {
+    pub direction: RouteDirection,
+    /// Remaining stops of the ride with predicted arrival times, in travel order.
+    pub arrivals: Vec<(Stop, NaiveDateTime)>,
+}
+
+impl EtaService {
+    pub fn new(route_service: Arc<RouteService>) -> Self {
+        Self {
+            route_service,
+            predictions: RwLock::default(),
+        }
+    }
+
+    /// Predicted arrivals at the stop, ordered by time.
+    pub fn eta(&self, stop: &str, direction: RouteDirection) -> Vec<(CarLicense, NaiveDateTime)> {
+        let mut etas = self
+            .predictions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, p)| p.direction == direction)
+            .filter_map(|(license, p)| {
+                p.arrivals
+                    .iter()
+                    .find(|(s, _)| s.name == stop)
+                    .map(|(_, time)| (license.clone(), *time))
+            })
+            .collect::<Vec<_>>();
+        etas.sort_by_key(|(_, time)| *time);
+        etas
+    }
+
+    pub fn prediction(&self, car_license: &str) -> Option<Prediction> {
+        self.predictions.read().unwrap().get(car_license).cloned()
+    }
+
+    /// Re-predicts arrivals for the bus at the position along the route of the ride.
+    pub fn update(
+        &self,
+        location: &Location,
+        ride: &Ride,
+        position: &RoutePosition,
+    ) -> Option<Prediction> {
+        let stops = self.route_service.ride_stops(ride);
+        let Some(prediction) = self
+            .route_service
+            .direction(ride)
+            .and_then(|direction| predict(location, ride, direction, &stops, position))
+        else {
+            self.forget(&location.car_license);
+            return None;
+        };
+
+        self.predictions
+            .write()
+            .unwrap()
+            .insert(location.car_license.clone(), prediction.clone());
+        Some(prediction)
+    }
+
+    pub fn forget(&self, car_license: &str) {
+        self.predictions.write().unwrap().remove(car_license);
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn predict(
+    location: &Location,
+    ride: &Ride,
+    direction: RouteDirection,
+    stops: &[Stop],
+    position: &RoutePosition,
+) -> Option<Prediction> {
+    let next_index = stops.iter().position(|s| s.name == position.next.name)?;
+
+    let ride_distance = stops
+        .windows(2)
+        .map(|w| w[0].coordinates.distance_to(w[1].coordinates))
+        .sum::<f64>();
+    let ride_duration = (ride.arrival - ride.departure).num_seconds();
+    if ride_duration <= 0 || ride_distance <= 0.0 {
+        return None;
+    }
+    let scheduled_speed = ride_distance / ride_duration as f64;
+    let current_speed = f64::from(location.speed) / 3.6;
+
+    // A bus which is still loading leaves the terminal not earlier than scheduled.
+    let departure = location.date_time.date().and_time(ride.departure);
+    let start = location.date_time.max(departure);
+
+    let segment = position
+        .previous
+        .coordinates
+        .distance_to(position.next.coordinates);
+    let mut remaining = (1.0 - position.fraction) * segment;
+    let mut arrivals = Vec::with_capacity(stops.len() - next_index);
+    for (index, stop) in stops.iter().enumerate().skip(next_index) {
+        if index > next_index {
+            remaining += stops[index - 1].coordinates.distance_to(stop.coordinates);
+        }
+
+        let speed = if location.speed < STANDING_SPEED_KMH {
+            scheduled_speed
+        } else {
+            let weight = (-remaining / CURRENT_SPEED_HORIZON_M).exp();
+            weight.mul_add(current_speed, (1.0 - weight) * scheduled_speed)
+        };
+
+        let travel = TimeDelta::try_seconds((remaining / speed).round() as i64)?;
+        arrivals.push((stop.clone(), start + travel));
+    }
+
+    Some(Prediction {
+        direction,
+        arrivals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::{
+        domain::{Coordinates, Terminal},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    fn ride() -> Ride {
+        Ride {
+            name: "Bus7".to_string(),
+            start: Terminal::Airport,
+            stop: Terminal::Rawai,
+            loading: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        }
+    }
+
+    fn location(coordinates: Coordinates, time: NaiveTime, speed: u32) -> Location {
+        serde_json::from_str(
+            &serde_json::json!({
+            "deviceno": "0088007439",
+            "lat": coordinates.latitude.0.to_string(),
+            "lng": coordinates.longitude.0.to_string(),
+            "state": 1,
+            "speed": speed,
+            "direction": 180.0,
+            "altitude": 10,
+            "dateTime": NaiveDate::from_ymd_opt(2024, 3, 20)
+                .unwrap()
+                .and_time(time)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            "vid": 1,
+            "carlicense": "10-1152",
+            "groupName": "Phuket Smart Bus"
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    fn sut() -> (EtaService, Vec<Stop>) {
+        let route_service = Arc::new(RouteService::new(Arc::new(FetchService::for_tests())));
+        let stops = route_service.ride_stops(&ride());
+        (EtaService::new(route_service), stops)
+    }
+
+    /// The bus standing at the first stop of the ride.
+    fn position(stops: &[Stop]) -> RoutePosition {
+        RoutePosition {
+            previous: stops[0].clone(),
+            next: stops[1].clone(),
+            distance: 0.0,
+            fraction: 0.0,
+            offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn predicts_remaining_stops_in_order() {
+        let (sut, stops) = sut();
+        let time = NaiveTime::from_hms_opt(15, 0, 0).unwrap();
+
+        let prediction = sut
+            .update(
+                &location(stops[0].coordinates, time, 0),
+                &ride(),
+                &position(&stops),
+            )
+            .expect("Prediction");
+
+        assert_eq!(stops.len() - 1, prediction.arrivals.len());
+        assert!(prediction.arrivals.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        // Standing bus at the terminal follows the schedule.
+        let (last, arrival) = prediction.arrivals.last().unwrap();
+        assert_eq!("Rawai Beach", last.name);
+        assert!((ride().arrival - arrival.time()).num_seconds().abs() <= 1);
+    }
+
+    #[test]
+    fn loading_bus_departs_on_schedule() {
+        let (sut, stops) = sut();
+        let time = NaiveTime::from_hms_opt(14, 40, 0).unwrap();
+
+        let prediction = sut
+            .update(
+                &location(stops[0].coordinates, time, 0),
+                &ride(),
+                &position(&stops),
+            )
+            .unwrap();
+
+        let arrival = prediction.arrivals.last().unwrap().1.time();
+        assert!((ride().arrival - arrival).num_seconds().abs() <= 1);
+    }
+
+    #[test]
+    fn fast_bus_arrives_earlier() {
+        let (sut, stops) = sut();
+        let time = NaiveTime::from_hms_opt(15, 0, 0).unwrap();
+
+        let standing = sut
+            .update(
+                &location(stops[0].coordinates, time, 0),
+                &ride(),
+                &position(&stops),
+            )
+            .unwrap();
+        let moving = sut
+            .update(
+                &location(stops[0].coordinates, time, 60),
+                &ride(),
+                &position(&stops),
+            )
+            .unwrap();
+
+        assert!(moving.arrivals[0].1 < standing.arrivals[0].1);
+    }
+
+    #[test]
+    fn eta_by_stop() {
+        let (sut, stops) = sut();
+        let time = NaiveTime::from_hms_opt(15, 0, 0).unwrap();
+        sut.update(
+            &location(stops[0].coordinates, time, 30),
+            &ride(),
+            &position(&stops),
+        );
+
+        let etas = sut.eta("Kata Palm", RouteDirection(Terminal::Rawai));
+        assert_eq!(1, etas.len());
+        assert_eq!("10-1152", etas[0].0);
+
+        assert!(sut
+            .eta("Kata Palm", RouteDirection(Terminal::Airport))
+            .is_empty());
+    }
+}
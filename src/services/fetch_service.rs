@@ -1,119 +1,221 @@
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    RwLock, RwLockReadGuard,
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
 };
 
-use chrono::{NaiveDateTime, Utc};
-
 use crate::{
     config::Config,
-    domain::{fetch, Bus, Schedule, Stop},
+    data_diff,
+    data_source::{self, DataSource, Dataset},
+    domain::{Aliases, Bus, Schedule, Stop},
+    events::Event,
+    metrics::METRICS,
+    validation::{self, parse_sheet, SheetReport},
 };
 
+/// How soon a failed refresh is retried.
+const RETRY_INTERVAL: Duration = Duration::from_mins(1);
+
 pub struct FetchService {
     config: Config,
-    inner: RwLock<Inner>,
-    version: AtomicU64,
+    source: Box<dyn DataSource>,
+    snapshot: RwLock<Arc<Snapshot>>,
 }
 
+/// Reference data fetched at once, never changed after being published.
 #[derive(Debug, Default, Clone)]
-struct Inner {
-    buses: Vec<Bus>,
-    schedule: Vec<Schedule>,
-    stops: Vec<Stop>,
-    last_updated: NaiveDateTime,
+pub struct Snapshot {
+    /// Zero until the first successful fetch.
+    pub version: u64,
+    pub buses: Vec<Bus>,
+    pub schedule: Vec<Schedule>,
+    pub stops: Vec<Stop>,
 }
 
-impl Inner {
-    fn fetch(config: &Config) -> anyhow::Result<Self> {
-        Ok(Self {
-            buses: fetch(&config.buses_url)?,
-            schedule: fetch(&config.schedule_url)?,
-            stops: fetch(&config.stops_url)?,
-            last_updated: Utc::now().naive_local(),
-        })
-    }
-
-    #[cfg(test)]
-    fn for_tests() -> Self {
-        use crate::domain::{parse_list, TEST_BUSES, TEST_SCHEDULE, TEST_STOPS};
-        Self {
-            buses: parse_list(TEST_BUSES).unwrap(),
-            schedule: parse_list(TEST_SCHEDULE).unwrap(),
-            stops: parse_list(TEST_STOPS).unwrap(),
-            last_updated: Utc::now().naive_local() + chrono::TimeDelta::try_days(365).unwrap(),
-        }
+impl Snapshot {
+    fn fetch(
+        source: &dyn DataSource,
+        aliases: &Aliases,
+        version: u64,
+    ) -> anyhow::Result<(Self, Vec<SheetReport>)> {
+        let (buses, buses_report) = parse_sheet(source, Dataset::Buses, aliases)?;
+        let (schedule, schedule_report) = parse_sheet(source, Dataset::Schedule, aliases)?;
+        let (stops, stops_report) = parse_sheet(source, Dataset::Stops, aliases)?;
+        let snapshot = Self {
+            version,
+            buses,
+            schedule,
+            stops,
+        };
+        Ok((snapshot, vec![buses_report, schedule_report, stops_report]))
     }
 }
 
 impl FetchService {
     pub fn new(config: Config) -> Self {
+        let source = data_source::from_config(&config);
+        Self::with_source(config, source)
+    }
+
+    pub fn with_source(config: Config, source: Box<dyn DataSource>) -> Self {
         Self {
             config,
-            inner: RwLock::default(),
-            version: AtomicU64::new(1),
+            source,
+            snapshot: RwLock::default(),
         }
     }
 
     #[cfg(test)]
     pub fn for_tests() -> Self {
+        let source = data_source::MemorySource::embedded();
+        let (snapshot, _) = Snapshot::fetch(&source, &Aliases::new(), 1).unwrap();
         Self {
             config: Config::default(),
-            inner: RwLock::new(Inner::for_tests()),
-            version: AtomicU64::new(1),
+            source: Box::new(source),
+            snapshot: RwLock::new(Arc::new(snapshot)),
         }
     }
 
-    pub fn buses(&self) -> Vec<Bus> {
-        self.inner().buses.clone()
+    /// The latest published data, kept as is by the holder across refreshes.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
     }
+
     pub fn schedule(&self) -> Vec<Schedule> {
-        self.inner().schedule.clone()
+        self.snapshot().schedule.clone()
     }
     pub fn stops(&self) -> Vec<Stop> {
-        self.inner().stops.clone()
-    }
-    pub fn version(&self) -> u64 {
-        self.version.load(Ordering::Acquire)
+        self.snapshot().stops.clone()
     }
 
-    fn inner(&self) -> RwLockReadGuard<Inner> {
-        self.fetch_if_outdated();
-        self.inner.read().unwrap()
-    }
+    /// Fetches the data from the source and publishes it as the next version,
+    /// reporting what has changed since the previous one.
+    /// Blocks on the source, readers keep getting the previous snapshot meanwhile.
+    pub fn refresh(&self) -> anyhow::Result<()> {
+        Event::DataFetching.emit();
+        let previous = self.snapshot();
+        let snapshot = self
+            .fetch(previous.version + 1)
+            .inspect_err(|_| METRICS.data_refreshes.with_label_values(&["failure"]).inc())?;
 
-    fn fetch_if_outdated(&self) {
-        if (Utc::now().naive_local() - self.inner.read().unwrap().last_updated)
-            < self.config.update_interval
-        {
-            return;
+        let version = snapshot.version;
+        if previous.version > 0 {
+            for change in data_diff::diff(&previous, &snapshot) {
+                Event::DataChanged { version, change }.emit();
+            }
         }
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
 
-        {
-            let mut inner_guard = self.inner.write().unwrap();
-            // Write lock check
-            if (Utc::now().naive_local() - inner_guard.last_updated) <= self.config.update_interval
-            {
-                return;
+        METRICS.data_refreshed(version);
+        Event::DataRefreshed {
+            source: "Sheets".to_string(),
+            version,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Fetches the data, refusing it when too many rows are rejected.
+    fn fetch(&self, version: u64) -> anyhow::Result<Snapshot> {
+        let (snapshot, reports) =
+            Snapshot::fetch(self.source.as_ref(), &self.config.column_aliases, version)?;
+        for report in &reports {
+            for rejection in &report.rejections {
+                Event::RowRejected {
+                    dataset: report.dataset,
+                    rejection: rejection.clone(),
+                }
+                .emit();
             }
-            // Postpone other attempts by 1 minute
-            inner_guard.last_updated = Utc::now().naive_local() - self.config.update_interval
-                + chrono::TimeDelta::try_minutes(1).unwrap();
         }
+        validation::check(&reports, self.config.max_rejected_rows)?;
+        Ok(snapshot)
+    }
+}
 
-        println!("Fetching new data");
-        match Inner::fetch(&self.config) {
-            Ok(inner) => {
-                *self.inner.write().unwrap() = inner;
-                self.version.fetch_add(1, Ordering::AcqRel);
-                println!("Update completed, version {}", self.version());
+/// Refreshes the data every update interval, retrying failures sooner.
+/// The first refresh is up to the caller, waiting for the data before going on.
+pub async fn run_refresh(service: Arc<FetchService>) {
+    let interval = service
+        .config
+        .update_interval
+        .to_std()
+        .unwrap_or(RETRY_INTERVAL);
+
+    tokio::time::sleep(interval).await;
+    loop {
+        let service = service.clone();
+        let delay = match tokio::task::spawn_blocking(move || service.refresh()).await {
+            Ok(Ok(())) => interval,
+            Ok(Err(err)) => {
+                Event::FetchFailed {
+                    error: format!("{err:#}"),
+                    retry_in_sec: RETRY_INTERVAL.as_secs(),
+                }
+                .emit();
+                RETRY_INTERVAL
             }
             Err(err) => {
-                self.inner.write().unwrap().last_updated = Utc::now().naive_local()
-                    - self.config.update_interval
-                    + chrono::TimeDelta::try_minutes(1).unwrap();
-                eprintln!("Failed to fetch {err:#}, retry in 1 minute");
+                Event::failed("refresh data", &err.into()).emit();
+                RETRY_INTERVAL
             }
-        }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_source::MemorySource;
+
+    use super::*;
+
+    #[test]
+    fn refresh() {
+        let sut = FetchService::with_source(Config::default(), Box::new(MemorySource::embedded()));
+        assert_eq!(0, sut.snapshot().version);
+        assert!(sut.snapshot().buses.is_empty());
+        let before = sut.snapshot();
+
+        sut.refresh().unwrap();
+
+        assert_eq!(1, sut.snapshot().version);
+        assert_eq!(14, sut.snapshot().buses.len());
+        // Holders of the previous snapshot are not affected.
+        assert_eq!(0, before.version);
+        assert!(before.stops.is_empty());
+    }
+
+    #[test]
+    fn failed_refresh_keeps_snapshot() {
+        let sut = FetchService {
+            source: Box::new(MemorySource {
+                buses: b"not json".to_vec(),
+                ..MemorySource::embedded()
+            }),
+            ..FetchService::for_tests()
+        };
+
+        assert!(sut.refresh().is_err());
+
+        assert_eq!(1, sut.snapshot().version);
+        assert_eq!(14, sut.snapshot().buses.len());
+    }
+
+    #[test]
+    fn too_many_rejected_rows() {
+        let sut = FetchService {
+            config: Config {
+                max_rejected_rows: Some(2),
+                ..Config::default()
+            },
+            ..FetchService::for_tests()
+        };
+
+        // The buses sheet has 3 rows rejected.
+        assert!(sut.refresh().is_err());
+
+        assert_eq!(1, sut.snapshot().version);
+        assert_eq!(14, sut.snapshot().buses.len());
     }
 }
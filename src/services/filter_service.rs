@@ -0,0 +1,282 @@
+use std::{collections::HashMap, fmt::Display, sync::RwLock};
+
+use chrono::{NaiveDateTime, TimeDelta};
+
+use crate::domain::{Coordinates, Latitude, Location, Longitude};
+
+type CarLicense = String;
+
+/// Bounds of Phuket with a margin, longitude then latitude.
+const BOUNDS: ((f32, f32), (f32, f32)) = ((98.0, 98.7), (7.5, 8.5));
+/// The speed implied by a jump may exceed the reported one this many times.
+const JUMP_SPEED_FACTOR: f64 = 2.0;
+/// Allowance for the reported speed lagging behind, km/h.
+const JUMP_SPEED_MARGIN_KMH: f64 = 30.0;
+/// After this many jumps in a row the bus is rather where it is reported now.
+const MAX_REJECTED: usize = 3;
+/// Locations further apart start the track anew instead of extrapolating the motion.
+const MAX_GAP: TimeDelta = TimeDelta::minutes(2);
+/// Weight of the measured position against the predicted one.
+const ALPHA: f64 = 0.6;
+/// Weight of the measured velocity against the estimated one.
+const BETA: f64 = 0.2;
+
+/// Rejects impossible locations of the buses and smooths the rest with an alpha-beta filter.
+#[derive(Default)]
+pub struct FilterService {
+    states: RwLock<HashMap<CarLicense, State>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    date_time: NaiveDateTime,
+    /// The last accepted location as reported.
+    raw: Coordinates,
+    speed: u32,
+    /// Longitude and latitude, degrees.
+    filtered: (f64, f64),
+    /// Degrees per second.
+    velocity: (f64, f64),
+    rejected: usize,
+}
+
+/// Why a location has been rejected as an outlier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outlier {
+    /// Zero or outside Phuket.
+    InvalidCoordinates,
+    /// Not later than the last accepted location.
+    Backwards { last: NaiveDateTime },
+    /// Too far from the last accepted location for the reported speed, km/h implied.
+    Jump { speed: f64 },
+}
+
+impl Display for Outlier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidCoordinates => write!(f, "invalid coordinates"),
+            Self::Backwards { last } => write!(f, "backwards from {last}"),
+            Self::Jump { speed } => write!(f, "jump at {speed:.0} km/h"),
+        }
+    }
+}
+
+impl Outlier {
+    /// Label of the metrics.
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Self::InvalidCoordinates => "invalid_coordinates",
+            Self::Backwards { .. } => "backwards",
+            Self::Jump { .. } => "jump",
+        }
+    }
+}
+
+impl FilterService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the smoothed coordinates of the location, unless it is rejected.
+    pub fn filter(&self, location: &Location) -> Result<Coordinates, Outlier> {
+        let coordinates = location.coordinates;
+        let ((west, east), (south, north)) = BOUNDS;
+        if !(west..=east).contains(&coordinates.longitude.0)
+            || !(south..=north).contains(&coordinates.latitude.0)
+        {
+            return Err(Outlier::InvalidCoordinates);
+        }
+
+        let mut states = self.states.write().unwrap();
+        let state = states
+            .entry(location.car_license.clone())
+            .or_insert_with(|| State::new(location));
+        if state.date_time == location.date_time {
+            return Ok(state.coordinates());
+        }
+
+        // Restarting the track from a stale location would reject the current ones.
+        if location.date_time < state.date_time {
+            return Err(Outlier::Backwards {
+                last: state.date_time,
+            });
+        }
+
+        let hours = (location.date_time - state.date_time).as_seconds_f64() / 3600.0;
+        let speed = state.raw.distance_to(coordinates) / 1000.0 / hours;
+        let limit = f64::from(location.speed.max(state.speed))
+            .mul_add(JUMP_SPEED_FACTOR, JUMP_SPEED_MARGIN_KMH);
+        if speed > limit {
+            state.rejected += 1;
+            if state.rejected < MAX_REJECTED {
+                return Err(Outlier::Jump { speed });
+            }
+            *state = State::new(location);
+        } else if location.date_time - state.date_time > MAX_GAP {
+            *state = State::new(location);
+        } else {
+            state.update(location);
+        }
+        let filtered = state.coordinates();
+        drop(states);
+
+        Ok(filtered)
+    }
+}
+
+impl State {
+    fn new(location: &Location) -> Self {
+        Self {
+            date_time: location.date_time,
+            raw: location.coordinates,
+            speed: location.speed,
+            filtered: (
+                location.coordinates.longitude.0.into(),
+                location.coordinates.latitude.0.into(),
+            ),
+            velocity: (0.0, 0.0),
+            rejected: 0,
+        }
+    }
+
+    /// Predicts the position from the estimated velocity and corrects it by the location.
+    fn update(&mut self, location: &Location) {
+        let seconds = (location.date_time - self.date_time).as_seconds_f64();
+        // A standing bus does not drift with the velocity estimated before it stopped.
+        if location.speed == 0 {
+            self.velocity = (0.0, 0.0);
+        }
+        let predicted = (
+            self.velocity.0.mul_add(seconds, self.filtered.0),
+            self.velocity.1.mul_add(seconds, self.filtered.1),
+        );
+        let residual = (
+            f64::from(location.coordinates.longitude.0) - predicted.0,
+            f64::from(location.coordinates.latitude.0) - predicted.1,
+        );
+
+        self.filtered = (
+            ALPHA.mul_add(residual.0, predicted.0),
+            ALPHA.mul_add(residual.1, predicted.1),
+        );
+        self.velocity = (
+            (BETA * residual.0).mul_add(seconds.recip(), self.velocity.0),
+            (BETA * residual.1).mul_add(seconds.recip(), self.velocity.1),
+        );
+        self.date_time = location.date_time;
+        self.raw = location.coordinates;
+        self.speed = location.speed;
+        self.rejected = 0;
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    const fn coordinates(&self) -> Coordinates {
+        Coordinates::new(
+            Longitude(self.filtered.0 as f32),
+            Latitude(self.filtered.1 as f32),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    /// Meters of latitude per degree.
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+
+    /// A location `north` meters north of Phuket Airport, `seconds` after 15:00.
+    fn location(north: f64, seconds: i64, speed: u32) -> Location {
+        let date_time = NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(15, 0, 0)
+            .unwrap()
+            + TimeDelta::seconds(seconds);
+        serde_json::from_str(&format!(
+            r#"{{"deviceno":"0088007439","lat":"{}","lng":"98.30657","state":1,"speed":{speed},"direction":0,"altitude":12,"dateTime":"{}","vid":246,"carlicense":"10-1152","groupName":"Phuket Smart Bus"}}"#,
+            8.108_55 + north / METERS_PER_DEGREE,
+            date_time.format("%Y-%m-%d %H:%M:%S")
+        ))
+        .unwrap()
+    }
+
+    fn north(sut: &FilterService, location: &Location) -> Result<f64, Outlier> {
+        let coordinates = sut.filter(location)?;
+        Ok((f64::from(coordinates.latitude.0) - 8.108_55) * METERS_PER_DEGREE)
+    }
+
+    #[test]
+    fn reject_invalid_coordinates() {
+        let sut = FilterService::new();
+        let mut location = location(0.0, 0, 30);
+        location.coordinates = Coordinates::new(Longitude(0.0), Latitude(0.0));
+
+        assert_eq!(Err(Outlier::InvalidCoordinates), sut.filter(&location));
+    }
+
+    #[test]
+    fn reject_backwards() {
+        let sut = FilterService::new();
+        sut.filter(&location(0.0, 30, 30)).unwrap();
+
+        assert!(matches!(
+            sut.filter(&location(0.0, 0, 30)),
+            Err(Outlier::Backwards { .. })
+        ));
+    }
+
+    #[test]
+    fn reject_persistent_backwards() {
+        let sut = FilterService::new();
+        sut.filter(&location(0.0, 60, 30)).unwrap();
+
+        for seconds in 0..4 {
+            assert!(matches!(
+                sut.filter(&location(0.0, seconds, 30)),
+                Err(Outlier::Backwards { .. })
+            ));
+        }
+        assert!(north(&sut, &location(100.0, 70, 30)).is_ok());
+    }
+
+    #[test]
+    fn reject_jump() {
+        let sut = FilterService::new();
+        sut.filter(&location(0.0, 0, 36)).unwrap();
+        // 10 m/s as reported.
+        assert!(north(&sut, &location(300.0, 30, 36)).is_ok());
+
+        // 5 km in half a minute.
+        assert!(matches!(
+            sut.filter(&location(5_000.0, 60, 36)),
+            Err(Outlier::Jump { .. })
+        ));
+        assert!(north(&sut, &location(600.0, 60, 36)).is_ok());
+    }
+
+    #[test]
+    fn accept_persistent_jump() {
+        let sut = FilterService::new();
+        sut.filter(&location(0.0, 0, 36)).unwrap();
+
+        assert!(sut.filter(&location(5_000.0, 30, 36)).is_err());
+        assert!(sut.filter(&location(5_300.0, 60, 36)).is_err());
+        let restarted = north(&sut, &location(5_600.0, 90, 36)).unwrap();
+
+        assert!((restarted - 5_600.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn smooth_standing_bus() {
+        let sut = FilterService::new();
+        sut.filter(&location(0.0, 0, 0)).unwrap();
+
+        let jitter = north(&sut, &location(20.0, 30, 0)).unwrap();
+        let back = north(&sut, &location(0.0, 60, 0)).unwrap();
+
+        assert!(jitter < 20.0);
+        assert!(back.abs() < jitter);
+    }
+}
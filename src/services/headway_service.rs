@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::Serialize;
+
+use crate::{
+    config::HeadwayConfig,
+    domain::{Ride, RouteDirection},
+};
+
+use super::{RouteService, Vehicle};
+
+type CarLicense = String;
+
+/// Buses not seen for this long are no longer driving.
+const STALE: TimeDelta = TimeDelta::minutes(5);
+
+/// Compares the headways between the buses following each other in a direction
+/// with the headways of their schedule.
+pub struct HeadwayService {
+    config: HeadwayConfig,
+    route_service: Arc<RouteService>,
+    /// Headways of the buses behind the bus ahead of them.
+    headways: RwLock<HashMap<CarLicense, Headway>>,
+    /// Time of the latest location, headways measured long before it are stale.
+    latest: RwLock<Option<NaiveDateTime>>,
+}
+
+/// The headway of a bus behind the bus ahead of it in the same direction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Headway {
+    pub direction: RouteDirection,
+    pub car_license: CarLicense,
+    /// The bus ahead.
+    pub leader: CarLicense,
+    /// Distance along the route of the direction to the bus ahead, meters.
+    pub distance: f64,
+    /// Estimated time between the buses, the time the bus behind takes to cover the distance
+    /// at the average speed of its ride by the schedule, seconds.
+    pub estimated_time: i64,
+    /// Time between the rides of the buses at the stops both serve, seconds.
+    pub scheduled: Option<i64>,
+    pub status: HeadwayStatus,
+    /// Time of the location of the bus the headway was measured at.
+    pub date_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadwayStatus {
+    #[default]
+    Regular,
+    Bunching,
+    Gap,
+}
+
+/// A bus driving its ride, at a distance along the route of the direction.
+struct Driving<'a> {
+    vehicle: &'a Vehicle,
+    ride: &'a Ride,
+    direction: RouteDirection,
+    distance: f64,
+}
+
+impl HeadwayService {
+    pub fn new(config: HeadwayConfig, route_service: Arc<RouteService>) -> Self {
+        Self {
+            config,
+            route_service,
+            headways: RwLock::default(),
+            latest: RwLock::default(),
+        }
+    }
+
+    /// Current headways ordered by direction, then by car license.
+    /// Headways of the buses no longer seen by the latest location are dropped.
+    pub fn all(&self) -> Vec<Headway> {
+        let latest = *self.latest.read().unwrap();
+        let mut stored = self.headways.write().unwrap();
+        if let Some(latest) = latest {
+            stored.retain(|_, h| latest - h.date_time <= STALE);
+        }
+        let mut headways = stored.values().cloned().collect::<Vec<_>>();
+        drop(stored);
+        headways.sort_by(|a, b| (a.direction, &a.car_license).cmp(&(b.direction, &b.car_license)));
+        headways
+    }
+
+    /// Measures the headway of the vehicle behind the closest of the `vehicles` ahead of it,
+    /// and of the vehicles following it, and returns the ones whose status has changed.
+    pub fn update(&self, vehicle: &Vehicle, vehicles: &[Vehicle]) -> Vec<Headway> {
+        let mut latest = self.latest.write().unwrap();
+        *latest = (*latest).max(Some(vehicle.date_time));
+        drop(latest);
+
+        let followers = self
+            .headways
+            .read()
+            .unwrap()
+            .values()
+            .filter(|h| h.leader == vehicle.car_license)
+            .map(|h| h.car_license.clone())
+            .collect::<Vec<_>>();
+
+        let mut changed = Vec::from_iter(self.remeasure(vehicle, vehicles));
+        for follower in vehicles
+            .iter()
+            .filter(|v| followers.contains(&v.car_license))
+        {
+            if vehicle.date_time - follower.date_time > STALE {
+                self.forget(&follower.car_license);
+            } else {
+                changed.extend(self.remeasure(follower, vehicles));
+            }
+        }
+        changed
+    }
+
+    pub fn forget(&self, car_license: &str) {
+        self.headways.write().unwrap().remove(car_license);
+    }
+
+    /// Stores the headway of the vehicle and returns it when its status has changed.
+    fn remeasure(&self, vehicle: &Vehicle, vehicles: &[Vehicle]) -> Option<Headway> {
+        let Some(headway) = self.measure(vehicle, vehicles) else {
+            let previous = self.headways.write().unwrap().remove(&vehicle.car_license);
+            // The gap or bunching is over with no bus ahead.
+            return previous
+                .filter(|h| h.status != HeadwayStatus::Regular)
+                .map(|h| Headway {
+                    status: HeadwayStatus::Regular,
+                    ..h
+                });
+        };
+
+        let previous = self
+            .headways
+            .write()
+            .unwrap()
+            .insert(vehicle.car_license.clone(), headway.clone());
+        (previous.map(|h| h.status).unwrap_or_default() != headway.status).then_some(headway)
+    }
+
+    fn measure(&self, vehicle: &Vehicle, vehicles: &[Vehicle]) -> Option<Headway> {
+        let follower = self.driving(vehicle)?;
+        let leader = vehicles
+            .iter()
+            .filter(|v| v.car_license != vehicle.car_license)
+            .filter(|v| vehicle.date_time - v.date_time <= STALE)
+            .filter_map(|v| self.driving(v))
+            .filter(|d| d.direction == follower.direction && d.distance > follower.distance)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))?;
+
+        let distance = leader.distance - follower.distance;
+        let time = distance / self.scheduled_speed(follower.ride)?;
+        let scheduled = self.scheduled_headway(follower.ride, leader.ride);
+        #[allow(clippy::cast_precision_loss)]
+        let status = match scheduled.map(|s| s as f64) {
+            Some(s) if time < s * self.config.bunching => HeadwayStatus::Bunching,
+            Some(s) if time > s * self.config.gap => HeadwayStatus::Gap,
+            _ => HeadwayStatus::Regular,
+        };
+
+        #[allow(clippy::cast_possible_truncation)]
+        Some(Headway {
+            direction: follower.direction,
+            car_license: vehicle.car_license.clone(),
+            leader: leader.vehicle.car_license.clone(),
+            distance,
+            estimated_time: time.round() as i64,
+            scheduled,
+            status,
+            date_time: vehicle.date_time,
+        })
+    }
+
+    /// The vehicle on its ride, unless still loading at the start terminal.
+    fn driving<'a>(&self, vehicle: &'a Vehicle) -> Option<Driving<'a>> {
+        let ride = vehicle.ride.as_ref()?;
+        if vehicle.date_time.time() < ride.departure {
+            return None;
+        }
+        Some(Driving {
+            vehicle,
+            ride,
+            direction: vehicle.direction?,
+            distance: self
+                .route_service
+                .direction_distance(ride, vehicle.route_distance?)?,
+        })
+    }
+
+    /// Meters per second.
+    #[allow(clippy::cast_precision_loss)]
+    fn scheduled_speed(&self, ride: &Ride) -> Option<f64> {
+        let distance = self
+            .route_service
+            .ride_stops(ride)
+            .windows(2)
+            .map(|w| w[0].coordinates.distance_to(w[1].coordinates))
+            .sum::<f64>();
+        let duration = (ride.arrival - ride.departure).num_seconds();
+        (duration > 0 && distance > 0.0).then(|| distance / duration as f64)
+    }
+
+    /// Seconds between the scheduled times of the rides at the first stop both serve.
+    fn scheduled_headway(&self, follower: &Ride, leader: &Ride) -> Option<i64> {
+        let leader = self.route_service.timetable(leader);
+        self.route_service
+            .timetable(follower)
+            .into_iter()
+            .find_map(|(stop, time)| {
+                let (_, leader_time) = leader.iter().find(|(s, _)| s.name == stop.name)?;
+                Some((time - *leader_time).num_seconds())
+            })
+            .filter(|s| *s > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rstest::rstest;
+
+    use crate::{
+        domain::{Location, Terminal},
+        services::{FetchService, RideService},
+    };
+
+    use super::*;
+
+    fn sut() -> HeadwayService {
+        HeadwayService::new(
+            HeadwayConfig::default(),
+            Arc::new(RouteService::new(Arc::new(FetchService::for_tests()))),
+        )
+    }
+
+    /// The bus on the ride of the position at the time, `distance` meters along it.
+    fn vehicle(car_license: &str, position: &str, minute: u32, distance: f64) -> Vehicle {
+        let date_time = NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(16, minute, 0)
+            .unwrap();
+        let location: Location = serde_json::from_str(&format!(
+            r#"{{"deviceno":"0088007439","lat":"8.089848","lng":"98.313305","state":1,"speed":38,"direction":160.2,"altitude":12,"dateTime":"{}","vid":246,"carlicense":"{car_license}","groupName":"Phuket Smart Bus"}}"#,
+            date_time.format("%Y-%m-%d %H:%M:%S")
+        ))
+        .unwrap();
+        let mut vehicle = Vehicle::from(&location);
+        vehicle.ride =
+            RideService::new(Arc::new(FetchService::for_tests())).get(position, date_time.time());
+        vehicle.direction = Some(RouteDirection(Terminal::Rawai));
+        vehicle.route_distance = Some(distance);
+        vehicle
+    }
+
+    #[rstest]
+    // Half an hour apart by the schedule, about 11 km.
+    #[case(10_000.0, None)]
+    #[case(1_000.0, Some(HeadwayStatus::Bunching))]
+    #[case(30_000.0, Some(HeadwayStatus::Gap))]
+    fn status(#[case] distance: f64, #[case] expected: Option<HeadwayStatus>) {
+        let sut = sut();
+        let leader = vehicle("10-1152", "Bus7", 5, 35_000.0);
+        let follower = vehicle("10-1155", "Bus8", 5, 35_000.0 - distance);
+
+        let changed = sut.update(&follower, &[leader, follower.clone()]);
+
+        assert_eq!(expected, changed.first().map(|h| h.status));
+        let headway = &sut.all()[0];
+        assert_eq!("10-1152", headway.leader);
+        assert_eq!(Some(1800), headway.scheduled);
+    }
+
+    #[test]
+    fn restore() {
+        let sut = sut();
+        let leader = [vehicle("10-1152", "Bus7", 5, 35_000.0)];
+
+        let bunching = vehicle("10-1155", "Bus8", 5, 34_000.0);
+        assert!(!sut.update(&bunching, &leader).is_empty());
+        assert!(sut.update(&bunching, &leader).is_empty());
+
+        let regular = vehicle("10-1155", "Bus8", 6, 25_000.0);
+        assert_eq!(
+            Some(HeadwayStatus::Regular),
+            sut.update(&regular, &leader).first().map(|h| h.status)
+        );
+    }
+
+    #[test]
+    fn ignore_stale_and_loading_buses() {
+        let sut = sut();
+        let stale = vehicle("10-1152", "Bus7", 0, 35_000.0);
+        // Bus5 departs at 16:00 from the airport, still loading at 15:55.
+        let mut loading = vehicle("10-1205", "Bus5", 0, 0.0);
+        loading.date_time -= TimeDelta::minutes(5);
+        let follower = vehicle("10-1155", "Bus8", 10, 34_000.0);
+
+        assert!(sut.update(&follower, &[stale]).is_empty());
+        assert!(sut.update(&loading, &[follower]).is_empty());
+        assert!(sut.all().is_empty());
+    }
+
+    #[test]
+    fn remeasure_follower_when_leader_moves() {
+        let sut = sut();
+        let leader = vehicle("10-1152", "Bus7", 5, 35_000.0);
+        let follower = vehicle("10-1155", "Bus8", 5, 34_000.0);
+        sut.update(&follower, &[leader, follower.clone()]);
+
+        // The leader pulls away while the follower has not reported.
+        let leader = vehicle("10-1152", "Bus7", 7, 45_000.0);
+        let changed = sut.update(&leader, &[leader.clone(), follower]);
+
+        assert_eq!(
+            vec![("10-1155", HeadwayStatus::Regular)],
+            changed
+                .iter()
+                .map(|h| (h.car_license.as_str(), h.status))
+                .collect::<Vec<_>>()
+        );
+        assert!((sut.all()[0].distance - 11_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn expire_stale_headways() {
+        let sut = sut();
+        let leader = vehicle("10-1152", "Bus7", 5, 35_000.0);
+        let follower = vehicle("10-1155", "Bus8", 5, 34_000.0);
+        sut.update(&follower, &[leader, follower.clone()]);
+
+        // Another bus reporting later, while these two no longer do.
+        sut.update(&vehicle("10-1205", "Bus5", 10, 0.0), &[]);
+        assert_eq!(1, sut.all().len());
+        sut.update(&vehicle("10-1205", "Bus5", 11, 100.0), &[]);
+        assert!(sut.all().is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    config::OffRouteConfig,
+    domain::{Location, Ride},
+};
+
+type CarLicense = String;
+
+/// A reported off-route bus is back on route within this fraction of the distance,
+/// so a bus driving along the threshold does not flap between the two.
+const REJOIN_FACTOR: f64 = 0.5;
+
+pub struct OffRouteService {
+    config: OffRouteConfig,
+    states: RwLock<HashMap<CarLicense, State>>,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    ride: Ride,
+    /// When the bus has left the route.
+    since: NaiveDateTime,
+    reported: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OffRouteEvent {
+    /// The bus has stayed away from the route for longer than configured.
+    OffRoute {
+        car_license: CarLicense,
+        ride: Ride,
+        since: NaiveDateTime,
+        deviation: f64,
+    },
+    /// The reported off-route bus is well within the distance from the route again.
+    BackOnRoute {
+        car_license: CarLicense,
+        ride: Ride,
+        deviation: f64,
+    },
+    /// The reported off-route bus is no longer tracked on its ride, e.g. the ride has ended.
+    Abandoned { car_license: CarLicense, ride: Ride },
+}
+
+impl OffRouteService {
+    pub fn new(config: OffRouteConfig) -> Self {
+        Self {
+            config,
+            states: RwLock::default(),
+        }
+    }
+
+    pub fn is_off_route(&self, car_license: &str) -> bool {
+        self.states
+            .read()
+            .unwrap()
+            .get(car_license)
+            .is_some_and(|s| s.reported)
+    }
+
+    /// Tracks the distance of the bus from the route, `deviation` meters,
+    /// and returns an event when the bus leaves or rejoins the route.
+    pub fn update(
+        &self,
+        location: &Location,
+        ride: &Ride,
+        deviation: f64,
+    ) -> Option<OffRouteEvent> {
+        let mut states = self.states.write().unwrap();
+
+        let reported = states
+            .get(&location.car_license)
+            .is_some_and(|s| s.reported);
+        let rejoin = if reported {
+            self.config.distance * REJOIN_FACTOR
+        } else {
+            self.config.distance
+        };
+        if deviation <= rejoin {
+            states.remove(&location.car_license);
+            drop(states);
+            return reported.then(|| OffRouteEvent::BackOnRoute {
+                car_license: location.car_license.clone(),
+                ride: ride.clone(),
+                deviation,
+            });
+        }
+
+        let state = states
+            .entry(location.car_license.clone())
+            .or_insert_with(|| State {
+                ride: ride.clone(),
+                since: location.date_time,
+                reported: false,
+            });
+        if state.reported || location.date_time - state.since < self.config.duration {
+            return None;
+        }
+        state.reported = true;
+        let since = state.since;
+        drop(states);
+
+        Some(OffRouteEvent::OffRoute {
+            car_license: location.car_license.clone(),
+            ride: ride.clone(),
+            since,
+            deviation,
+        })
+    }
+
+    /// Stops tracking the bus, returning an event when it has been reported off-route.
+    pub fn forget(&self, car_license: &str) -> Option<OffRouteEvent> {
+        let state = self.states.write().unwrap().remove(car_license)?;
+        state.reported.then(|| OffRouteEvent::Abandoned {
+            car_license: car_license.to_string(),
+            ride: state.ride,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime, TimeDelta};
+    use rstest::rstest;
+
+    use crate::domain::Terminal;
+
+    use super::*;
+
+    fn ride() -> Ride {
+        Ride {
+            name: "Bus7".to_string(),
+            start: Terminal::Airport,
+            stop: Terminal::Rawai,
+            loading: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        }
+    }
+
+    fn location(seconds: i64) -> Location {
+        let date_time = NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(15, 10, 0)
+            .unwrap()
+            + TimeDelta::seconds(seconds);
+        serde_json::from_str(&format!(
+            r#"{{"deviceno":"0088007439","lat":"8.089848","lng":"98.313305","state":1,"speed":38,"direction":160.2,"altitude":12,"dateTime":"{}","vid":246,"carlicense":"10-1152","groupName":"Phuket Smart Bus"}}"#,
+            date_time.format("%Y-%m-%d %H:%M:%S")
+        ))
+        .unwrap()
+    }
+
+    fn sut() -> OffRouteService {
+        OffRouteService::new(OffRouteConfig {
+            distance: 100.0,
+            duration: TimeDelta::seconds(60),
+        })
+    }
+
+    #[test]
+    fn off_and_back_on_route() {
+        let sut = sut();
+
+        assert_eq!(None, sut.update(&location(0), &ride(), 300.0));
+        assert_eq!(None, sut.update(&location(30), &ride(), 400.0));
+        assert!(!sut.is_off_route("10-1152"));
+
+        let Some(OffRouteEvent::OffRoute {
+            since, deviation, ..
+        }) = sut.update(&location(60), &ride(), 500.0)
+        else {
+            panic!("Expected off-route event");
+        };
+        assert_eq!(location(0).date_time, since);
+        assert!((deviation - 500.0).abs() < f64::EPSILON);
+        assert!(sut.is_off_route("10-1152"));
+
+        // Reported once.
+        assert_eq!(None, sut.update(&location(90), &ride(), 500.0));
+
+        assert!(matches!(
+            sut.update(&location(120), &ride(), 20.0),
+            Some(OffRouteEvent::BackOnRoute { .. })
+        ));
+        assert!(!sut.is_off_route("10-1152"));
+    }
+
+    #[test]
+    fn rejoin_well_within_distance() {
+        let sut = sut();
+        sut.update(&location(0), &ride(), 300.0);
+        assert!(sut.update(&location(60), &ride(), 300.0).is_some());
+
+        // A parallel street along the threshold.
+        assert_eq!(None, sut.update(&location(70), &ride(), 90.0));
+        assert_eq!(None, sut.update(&location(80), &ride(), 110.0));
+        assert_eq!(None, sut.update(&location(90), &ride(), 80.0));
+        assert!(sut.is_off_route("10-1152"));
+
+        assert!(matches!(
+            sut.update(&location(100), &ride(), 40.0),
+            Some(OffRouteEvent::BackOnRoute { .. })
+        ));
+    }
+
+    #[test]
+    fn forget_reported() {
+        let sut = sut();
+        sut.update(&location(0), &ride(), 300.0);
+        assert_eq!(None, sut.forget("10-1152"));
+
+        sut.update(&location(0), &ride(), 300.0);
+        sut.update(&location(60), &ride(), 300.0);
+        assert!(matches!(
+            sut.forget("10-1152"),
+            Some(OffRouteEvent::Abandoned { .. })
+        ));
+        assert!(!sut.is_off_route("10-1152"));
+    }
+
+    #[rstest]
+    #[case::short_detour(&[(0, 300.0), (30, 300.0), (50, 50.0), (70, 300.0), (100, 300.0)])]
+    #[case::within_distance(&[(0, 90.0), (60, 90.0), (120, 100.0)])]
+    fn stays_on_route(#[case] updates: &[(i64, f64)]) {
+        let sut = sut();
+
+        for (seconds, deviation) in updates {
+            assert_eq!(None, sut.update(&location(*seconds), &ride(), *deviation));
+        }
+        assert!(!sut.is_off_route("10-1152"));
+    }
+}
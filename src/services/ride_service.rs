@@ -10,7 +10,7 @@ use chrono::NaiveTime;
 use itertools::Itertools;
 use rangemap::RangeMap;
 
-use crate::domain::Ride;
+use crate::{domain::Ride, events::Event};
 
 use super::FetchService;
 
@@ -40,12 +40,38 @@ impl RideService {
             .cloned()
     }
 
+    /// All rides of the position, ordered by departure.
+    pub fn rides(&self, pos: &str) -> Vec<Ride> {
+        self.update_if_neeeded();
+
+        self.rides
+            .read()
+            .unwrap()
+            .get(pos)
+            .map(|r| r.iter().map(|(_, ride)| ride.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Positions with any ride, ordered by name.
+    pub fn positions(&self) -> Vec<String> {
+        self.update_if_neeeded();
+
+        self.rides
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .sorted()
+            .collect()
+    }
+
     fn update_if_neeeded(&self) {
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        let snapshot = self.fetch_service.snapshot();
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
-        let schedule = self.fetch_service.schedule();
+        let schedule = snapshot.schedule.clone();
         let mut rides = HashMap::new();
 
         for (position, schedules) in schedule
@@ -63,18 +89,19 @@ impl RideService {
             rides.insert(position, ranges);
         }
 
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
         *self.rides.write().unwrap() = rides;
         self.current_version
-            .store(self.fetch_service.version(), Ordering::Relaxed);
+            .store(snapshot.version, Ordering::Relaxed);
 
-        println!(
-            "Rides updated, version {}",
-            self.current_version.load(Ordering::Acquire)
-        );
+        Event::DataRefreshed {
+            source: "Rides".to_string(),
+            version: self.current_version.load(Ordering::Acquire),
+        }
+        .emit();
     }
 }
 
@@ -93,4 +120,16 @@ mod tests {
         let bus3 = sut.get("Bus3", NaiveTime::from_hms_opt(14, 0, 0).unwrap());
         assert!(bus3.is_none());
     }
+
+    #[test]
+    fn rides_of_position() {
+        let sut = RideService::new(Arc::new(FetchService::for_tests()));
+
+        let rides = sut.rides("Bus7");
+        assert_eq!(4, rides.len());
+        assert!(rides.windows(2).all(|w| w[0].departure < w[1].departure));
+
+        assert!(sut.rides("Bus42").is_empty());
+        assert_eq!(8, sut.positions().len());
+    }
 }
@@ -1,29 +1,20 @@
-use std::{
-    collections::BTreeMap,
-    iter::once,
-    ops::Bound::{Included, Unbounded},
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc, RwLock,
-    },
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
 };
 
-use itertools::Itertools;
-
-use crate::domain::{Coordinates, Latitude, RouteDirection, Stop, Terminal};
+use crate::{
+    domain::{Coordinates, Line, Network, Ride, RouteDirection, RoutePosition, Stop},
+    events::Event,
+};
+use chrono::NaiveTime;
 
 use super::FetchService;
 
 pub struct RouteService {
     fetch_service: Arc<FetchService>,
     current_version: AtomicU64,
-    inner: RwLock<Inner>,
-}
-
-#[derive(Debug, Clone, Default)]
-struct Inner {
-    north: BTreeMap<Latitude, Stop>,
-    south: BTreeMap<Latitude, Stop>,
+    network: RwLock<Network>,
 }
 
 impl RouteService {
@@ -31,82 +22,146 @@ impl RouteService {
         Self {
             fetch_service,
             current_version: AtomicU64::default(),
-            inner: RwLock::default(),
+            network: RwLock::default(),
         }
     }
 
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn locate(&self, dir: RouteDirection, pos: Coordinates) -> Option<(Stop, Stop)> {
+    /// Projects the location onto the route of the ride.
+    pub fn locate(&self, ride: &Ride, pos: Coordinates) -> Option<RoutePosition> {
         self.update_if_neeeded();
 
-        let stops = match dir {
-            RouteDirection::North => self.inner.read().unwrap().north.clone(),
-            RouteDirection::South => self.inner.read().unwrap().south.clone(),
-        };
+        self.network
+            .read()
+            .unwrap()
+            .pattern(ride.start, ride.stop)?
+            .route
+            .project(pos)
+    }
 
-        let mut previous_it = stops.range((Unbounded, Included(pos.latitude)));
-        let mut next_it = stops.range((Included(pos.latitude), Unbounded));
-
-        let (mut previous, mut next) = {
-            let (previous, next) = (previous_it.next_back(), next_it.next());
-            if previous == next {
-                (previous_it.next_back(), previous)
-            } else if previous.is_none() {
-                (next, next_it.next())
-            } else {
-                (previous, next)
-            }
-        };
+    /// The direction the ride travels the network in.
+    pub fn direction(&self, ride: &Ride) -> Option<RouteDirection> {
+        self.update_if_neeeded();
+
+        self.network
+            .read()
+            .unwrap()
+            .pattern(ride.start, ride.stop)
+            .map(|p| p.direction)
+    }
+
+    /// Distance along the route of the direction of the ride, from the distance along the ride.
+    pub fn direction_distance(&self, ride: &Ride, distance: f64) -> Option<f64> {
+        self.update_if_neeeded();
+
+        self.network
+            .read()
+            .unwrap()
+            .pattern(ride.start, ride.stop)
+            .map(|p| p.offset + distance)
+    }
+
+    /// The line the ride runs on.
+    pub fn line(&self, ride: &Ride) -> Option<Line> {
+        self.update_if_neeeded();
 
-        if dir == RouteDirection::South {
-            std::mem::swap(&mut previous, &mut next);
+        self.network
+            .read()
+            .unwrap()
+            .pattern(ride.start, ride.stop)
+            .map(|p| p.line.clone())
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        self.update_if_neeeded();
+
+        self.network.read().unwrap().lines()
+    }
+
+    /// Stops served by the ride in travel order, from the start to the destination terminal.
+    /// Rides may start or end in the middle of the route, e.g. at Kata or Patong.
+    pub fn ride_stops(&self, ride: &Ride) -> Vec<Stop> {
+        self.update_if_neeeded();
+
+        self.network
+            .read()
+            .unwrap()
+            .pattern(ride.start, ride.stop)
+            .map(|p| p.route.stops().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Scheduled times at every stop of the ride, interpolated by the distance
+    /// between the ride departure and arrival.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn timetable(&self, ride: &Ride) -> Vec<(Stop, NaiveTime)> {
+        let stops = self.ride_stops(ride);
+
+        let mut distances = Vec::with_capacity(stops.len());
+        let mut distance = 0.0;
+        for (index, stop) in stops.iter().enumerate() {
+            if index > 0 {
+                distance += stops[index - 1].coordinates.distance_to(stop.coordinates);
+            }
+            distances.push(distance);
         }
 
-        previous.map(|s| s.1.clone()).zip(next.map(|s| s.1.clone()))
+        let duration = (ride.arrival - ride.departure).num_seconds().max(0) as f64;
+        stops
+            .into_iter()
+            .zip(distances)
+            .map(|(stop, d)| {
+                let offset = if distance > 0.0 {
+                    (duration * d / distance).round() as u32
+                } else {
+                    0
+                };
+                (
+                    stop,
+                    ride.departure
+                        .overflowing_add_signed(chrono::TimeDelta::seconds(offset.into()))
+                        .0,
+                )
+            })
+            .collect()
     }
 
     fn update_if_neeeded(&self) {
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        let snapshot = self.fetch_service.snapshot();
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
-        let stops = self.fetch_service.stops();
-
-        let build = |terminal: Terminal| {
-            stops
-                .iter()
-                .filter(|s| s.route_direction == terminal)
-                .cloned()
-                .sorted_by_key(|s| s.order)
-                .chain(once(terminal.stop(&stops)))
-                .map(|s| (s.coordinates.latitude, s))
-                .collect()
-        };
-        let inner = Inner {
-            north: build(Terminal::Airport),
-            south: build(Terminal::Rawai),
-        };
+        let network = Network::build(
+            &snapshot.stops,
+            snapshot.schedule.iter().map(|s| (s.start, s.destination)),
+        );
 
-        if self.current_version.load(Ordering::Acquire) == self.fetch_service.version() {
+        if self.current_version.load(Ordering::Acquire) == snapshot.version {
             return;
         }
 
-        *self.inner.write().unwrap() = inner;
+        *self.network.write().unwrap() = network;
         self.current_version
-            .store(self.fetch_service.version(), Ordering::Relaxed);
+            .store(snapshot.version, Ordering::Relaxed);
 
-        println!(
-            "Routes updated, version {}",
-            self.current_version.load(Ordering::Acquire)
-        );
+        Event::DataRefreshed {
+            source: "Routes".to_string(),
+            version: self.current_version.load(Ordering::Acquire),
+        }
+        .emit();
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
     use rstest::rstest;
 
-    use crate::domain::Longitude;
+    use crate::domain::{Latitude, Longitude, Terminal};
 
     use super::*;
 
@@ -121,97 +176,189 @@ mod tests {
         RouteService::new(Arc::new(FetchService::for_tests()))
     }
 
+    fn ride(start: Terminal, stop: Terminal) -> Ride {
+        let time = NaiveTime::MIN;
+        Ride {
+            name: "Bus1".to_string(),
+            start,
+            stop,
+            loading: time,
+            departure: time,
+            arrival: time,
+        }
+    }
+
     #[test]
-    #[ignore]
+    #[ignore = "prints routes for manual inspection"]
     fn print_routes() {
         let sut = sut();
-        sut.update_if_neeeded();
 
-        println!(
-            "NORTH: {}",
-            sut.inner
-                .read()
-                .unwrap()
-                .north
-                .values()
-                .map(|s| s.name.as_str())
-                .join(" > ")
-        );
-        println!(
-            "SOUTH: {}",
-            sut.inner
-                .read()
-                .unwrap()
-                .south
-                .values()
-                .rev()
-                .map(|s| s.name.as_str())
-                .join(" > ")
-        );
+        for (start, stop) in [
+            (Terminal::Airport, Terminal::Rawai),
+            (Terminal::Rawai, Terminal::Airport),
+        ] {
+            println!(
+                "{start} - {stop}: {}",
+                sut.ride_stops(&ride(start, stop))
+                    .iter()
+                    .map(|s| s.name.as_str())
+                    .join(" > ")
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::airport_rawai(
+        Terminal::Airport,
+        Terminal::Rawai,
+        27,
+        "Phuket Airport",
+        "Rawai Beach"
+    )]
+    #[case::rawai_airport(
+        Terminal::Rawai,
+        Terminal::Airport,
+        27,
+        "Rawai Beach",
+        "Phuket Airport"
+    )]
+    #[case::kata_airport(Terminal::Kata, Terminal::Airport, 23, "Kata Palm", "Phuket Airport")]
+    #[case::airport_patong(
+        Terminal::Airport,
+        Terminal::Patong,
+        16,
+        "Phuket Airport",
+        "Patong PEA"
+    )]
+    fn ride_stops(
+        #[case] start: Terminal,
+        #[case] stop: Terminal,
+        #[case] expected_len: usize,
+        #[case] first: &str,
+        #[case] last: &str,
+    ) {
+        let stops = sut().ride_stops(&ride(start, stop));
+
+        assert_eq!(expected_len, stops.len());
+        assert_eq!(first, stops.first().unwrap().name);
+        assert_eq!(last, stops.last().unwrap().name);
+    }
+
+    #[test]
+    fn timetable() {
+        let ride = Ride {
+            name: "Bus1".to_string(),
+            start: Terminal::Kata,
+            stop: Terminal::Airport,
+            loading: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(6, 15, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(8, 55, 0).unwrap(),
+        };
+
+        let timetable = sut().timetable(&ride);
+
+        assert_eq!(23, timetable.len());
+        assert_eq!(("Kata Palm", ride.departure), {
+            let (stop, time) = timetable.first().unwrap();
+            (stop.name.as_str(), *time)
+        });
+        assert_eq!(("Phuket Airport", ride.arrival), {
+            let (stop, time) = timetable.last().unwrap();
+            (stop.name.as_str(), *time)
+        });
+        assert!(timetable.windows(2).all(|w| w[0].1 <= w[1].1));
     }
 
     #[rstest]
     #[case::south_airport(
-        RouteDirection::South,
+        Terminal::Airport,
+        Terminal::Rawai,
         AIRPORT,
         "Phuket Airport",
         "Thalang Public Health Office"
     )]
     #[case::south_near_airport(
-        RouteDirection::South,
+        Terminal::Airport,
+        Terminal::Rawai,
         NEAR_AIRPORT,
         "Phuket Airport",
         "Thalang Public Health Office"
     )]
     #[case::south_rat_uthit(
-        RouteDirection::South,
+        Terminal::Airport,
+        Terminal::Rawai,
         RAT_UTHIT,
         "Diamond Cliff Resort & Spa",
         "Indigo Patong"
     )]
-    #[case::south_near_rawai(RouteDirection::South, NEAR_RAWAI, "Sai Yuan", "Rawai Beach")]
-    #[case::south_rawai(RouteDirection::South, RAWAI, "Sai Yuan", "Rawai Beach")]
-    #[case::north_rawai(RouteDirection::North, RAWAI, "Rawai Beach", "Sai Yuan")]
-    #[case::north_near_rawai(RouteDirection::North, NEAR_RAWAI, "Rawai Beach", "Sai Yuan")]
+    #[case::south_near_rawai(
+        Terminal::Airport,
+        Terminal::Rawai,
+        NEAR_RAWAI,
+        "Sai Yuan",
+        "Rawai Beach"
+    )]
+    #[case::south_rawai(Terminal::Airport, Terminal::Rawai, RAWAI, "Sai Yuan", "Rawai Beach")]
+    #[case::north_rawai(Terminal::Rawai, Terminal::Airport, RAWAI, "Rawai Beach", "Sai Yuan")]
+    #[case::north_near_rawai(
+        Terminal::Rawai,
+        Terminal::Airport,
+        NEAR_RAWAI,
+        "Rawai Beach",
+        "Sai Yuan"
+    )]
     #[case::north_thawi_wong(
-        RouteDirection::North,
+        Terminal::Rawai,
+        Terminal::Airport,
         THAWI_WONG,
         "Bangla Patong",
         "Four Point Patong"
     )]
     #[case::north_near_airport(
-        RouteDirection::North,
+        Terminal::Rawai,
+        Terminal::Airport,
         NEAR_AIRPORT,
         "Thalang Public Health Office",
         "Phuket Airport"
     )]
     #[case::north_airport(
-        RouteDirection::North,
+        Terminal::Rawai,
+        Terminal::Airport,
         AIRPORT,
         "Thalang Public Health Office",
         "Phuket Airport"
     )]
+    #[case::patong_rat_uthit(
+        Terminal::Airport,
+        Terminal::Patong,
+        RAT_UTHIT,
+        "Diamond Cliff Resort & Spa",
+        "Indigo Patong"
+    )]
     fn locate(
-        #[case] direction: RouteDirection,
+        #[case] start: Terminal,
+        #[case] stop: Terminal,
         #[case] pos: Coordinates,
         #[case] previous_stop_name: &str,
         #[case] next_stop_name: &str,
     ) {
         let sut = sut();
 
-        let Some((prev, next)) = sut.locate(direction, pos) else {
+        let Some(position) = sut.locate(&ride(start, stop), pos) else {
             panic!("Failed to locate the stop")
         };
 
         println!(
-            "{} -{} <=> +{} {}",
-            prev.name,
-            prev.coordinates.distance_to(pos),
-            next.coordinates.distance_to(pos),
-            next.name
+            "{} -{} <=> +{} {}, {}m along, {}m off",
+            position.previous.name,
+            position.previous.coordinates.distance_to(pos),
+            position.next.coordinates.distance_to(pos),
+            position.next.name,
+            position.distance,
+            position.offset
         );
 
-        assert_eq!(prev.name, previous_stop_name);
-        assert_eq!(next.name, next_stop_name);
+        assert_eq!(position.previous.name, previous_stop_name);
+        assert_eq!(position.next.name, next_stop_name);
     }
 }
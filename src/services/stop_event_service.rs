@@ -0,0 +1,372 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::{
+    config::StopConfig,
+    domain::{Coordinates, Location, Ride, RoutePosition, Stop},
+};
+
+use super::RouteService;
+
+type CarLicense = String;
+
+/// GPS jitter of a standing bus, meters.
+const STILL_DISTANCE_M: f64 = 10.0;
+
+pub struct StopEventService {
+    config: StopConfig,
+    route_service: Arc<RouteService>,
+    states: RwLock<HashMap<CarLicense, State>>,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    ride: Ride,
+    last: Option<Coordinates>,
+    /// Stops of the ride up to this index are arrived at or passed.
+    settled: usize,
+    /// The stop announced as approached.
+    approached: Option<usize>,
+    /// The stop the bus is standing at.
+    dwell: Option<(usize, StopVisit)>,
+}
+
+/// A bus standing at a stop, from the first to the last location it was seen standing there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StopVisit {
+    pub car_license: CarLicense,
+    pub ride: String,
+    pub stop: String,
+    pub arrived_at: NaiveDateTime,
+    pub departed_at: NaiveDateTime,
+}
+
+impl StopVisit {
+    /// Seconds.
+    pub fn dwell(&self) -> i64 {
+        (self.departed_at - self.arrived_at).num_seconds()
+    }
+}
+
+/// Transitions of a bus between the stops of its ride.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopEvent {
+    /// The next stop is within the approach distance, meters.
+    Approaching {
+        stop: Stop,
+        distance: f64,
+    },
+    Arrived {
+        stop: Stop,
+    },
+    Departed {
+        visit: StopVisit,
+    },
+    /// The bus has gone by the stop without standing at it.
+    Passed {
+        stop: Stop,
+    },
+}
+
+impl StopEventService {
+    pub fn new(config: StopConfig, route_service: Arc<RouteService>) -> Self {
+        Self {
+            config,
+            route_service,
+            states: RwLock::default(),
+        }
+    }
+
+    /// Tracks the bus along the stops of the ride and returns the transitions since its
+    /// previous location, in order.
+    pub fn update(
+        &self,
+        location: &Location,
+        ride: &Ride,
+        position: &RoutePosition,
+    ) -> Vec<StopEvent> {
+        let stops = self.route_service.ride_stops(ride);
+        let Some(previous) = stops.iter().position(|s| s.name == position.previous.name) else {
+            return vec![];
+        };
+        let next = previous + 1;
+        let distance = |index: usize| stops[index].coordinates.distance_to(location.coordinates);
+
+        let mut states = self.states.write().unwrap();
+        let state = states
+            .entry(location.car_license.clone())
+            .and_modify(|s| {
+                if s.ride != *ride {
+                    *s = State::new(ride, previous);
+                }
+            })
+            .or_insert_with(|| State::new(ride, previous));
+
+        // The reported speed lags behind, a bus which has not moved since the previous
+        // location is standing too.
+        let standing = location.speed < self.config.speed
+            || state
+                .last
+                .is_some_and(|last| last.distance_to(location.coordinates) <= STILL_DISTANCE_M);
+        let first_seen = state.last.is_none();
+        state.last = Some(location.coordinates);
+        let dwell = state.dwell.as_ref().map(|(index, _)| *index);
+        // A bus jittering back to a stop it has left is not there again.
+        let at = [previous, next]
+            .into_iter()
+            .filter(|index| *index < stops.len())
+            .filter(|index| *index > state.settled || dwell == Some(*index) || first_seen)
+            .map(|index| (index, distance(index)))
+            .filter(|(_, distance)| *distance <= self.config.radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+            .filter(|_| standing);
+
+        let mut events = vec![];
+        match (&mut state.dwell, at) {
+            (Some((stop, visit)), Some(index)) if *stop == index => {
+                visit.departed_at = location.date_time;
+            }
+            (dwell, at) => {
+                if let Some((_, visit)) = dwell.take() {
+                    events.push(StopEvent::Departed { visit });
+                }
+                if let Some(index) = at {
+                    events.push(StopEvent::Arrived {
+                        stop: stops[index].clone(),
+                    });
+                    state.dwell = Some((
+                        index,
+                        StopVisit {
+                            car_license: location.car_license.clone(),
+                            ride: ride.name.clone(),
+                            stop: stops[index].name.clone(),
+                            arrived_at: location.date_time,
+                            departed_at: location.date_time,
+                        },
+                    ));
+                }
+            }
+        }
+
+        // Stops behind the bus not stood at, unless the bus is still by the stop and may yet stop.
+        let dwell = state.dwell.as_ref().map(|(index, _)| *index);
+        while state.settled < previous.max(dwell.unwrap_or_default()) {
+            let index = state.settled + 1;
+            if dwell == Some(index) {
+                state.settled = index;
+                continue;
+            }
+            if distance(index) <= self.config.radius {
+                break;
+            }
+            events.push(StopEvent::Passed {
+                stop: stops[index].clone(),
+            });
+            state.settled = index;
+        }
+
+        if next < stops.len()
+            && next > state.settled
+            && state.approached < Some(next)
+            && dwell != Some(next)
+            && distance(next) <= self.config.approach
+        {
+            state.approached = Some(next);
+            events.push(StopEvent::Approaching {
+                stop: stops[next].clone(),
+                distance: distance(next),
+            });
+        }
+        drop(states);
+
+        events
+    }
+
+    pub fn forget(&self, car_license: &str) {
+        self.states.write().unwrap().remove(car_license);
+    }
+}
+
+impl State {
+    fn new(ride: &Ride, previous: usize) -> Self {
+        Self {
+            ride: ride.clone(),
+            last: None,
+            // A bus first seen along the ride has gone by the stops behind it unnoticed.
+            settled: previous,
+            approached: None,
+            dwell: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime, TimeDelta};
+
+    use crate::{
+        domain::{Latitude, Longitude, Terminal},
+        services::FetchService,
+    };
+
+    use super::*;
+
+    fn ride() -> Ride {
+        Ride {
+            name: "Bus7".to_string(),
+            start: Terminal::Airport,
+            stop: Terminal::Rawai,
+            loading: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+            departure: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+            arrival: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        }
+    }
+
+    fn location(coordinates: Coordinates, seconds: i64, speed: u32) -> Location {
+        let date_time = NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(15, 10, 0)
+            .unwrap()
+            + TimeDelta::seconds(seconds);
+        serde_json::from_str(&format!(
+            r#"{{"deviceno":"0088007439","lat":"{}","lng":"{}","state":1,"speed":{speed},"direction":160.2,"altitude":12,"dateTime":"{}","vid":246,"carlicense":"10-1152","groupName":"Phuket Smart Bus"}}"#,
+            coordinates.latitude.0,
+            coordinates.longitude.0,
+            date_time.format("%Y-%m-%d %H:%M:%S")
+        ))
+        .unwrap()
+    }
+
+    /// A point `fraction` of the way between the stops.
+    fn between(a: &Stop, b: &Stop, fraction: f32) -> Coordinates {
+        let (a, b) = (a.coordinates, b.coordinates);
+        Coordinates::new(
+            Longitude((b.longitude.0 - a.longitude.0).mul_add(fraction, a.longitude.0)),
+            Latitude((b.latitude.0 - a.latitude.0).mul_add(fraction, a.latitude.0)),
+        )
+    }
+
+    fn sut() -> (StopEventService, Vec<Stop>) {
+        let route_service = Arc::new(RouteService::new(Arc::new(FetchService::for_tests())));
+        let stops = route_service.ride_stops(&ride());
+        (
+            StopEventService::new(StopConfig::default(), route_service),
+            stops,
+        )
+    }
+
+    /// Names of the events, e.g. `arrived Thalang Public Health Office`.
+    fn update(
+        sut: &StopEventService,
+        coordinates: Coordinates,
+        seconds: i64,
+        speed: u32,
+    ) -> Vec<String> {
+        let position = sut.route_service.locate(&ride(), coordinates).unwrap();
+        sut.update(&location(coordinates, seconds, speed), &ride(), &position)
+            .into_iter()
+            .map(|event| match event {
+                StopEvent::Approaching { stop, .. } => format!("approaching {}", stop.name),
+                StopEvent::Arrived { stop } => format!("arrived {}", stop.name),
+                StopEvent::Departed { visit } => {
+                    format!("departed {} after {}s", visit.stop, visit.dwell())
+                }
+                StopEvent::Passed { stop } => format!("passed {}", stop.name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arrive_and_depart() {
+        let (sut, stops) = sut();
+        let stop = stops[2].coordinates;
+
+        assert!(update(&sut, between(&stops[1], &stops[2], 0.2), 0, 30).is_empty());
+        assert_eq!(
+            vec![format!("approaching {}", stops[2].name)],
+            update(&sut, between(&stops[1], &stops[2], 0.99), 30, 30)
+        );
+        assert_eq!(
+            vec![format!("arrived {}", stops[2].name)],
+            update(&sut, stop, 60, 2)
+        );
+        assert!(update(&sut, stop, 90, 0).is_empty());
+        assert!(update(&sut, stop, 125, 3).is_empty());
+
+        let events = update(&sut, between(&stops[2], &stops[3], 0.3), 150, 25);
+        assert_eq!(format!("departed {} after 65s", stops[2].name), events[0]);
+    }
+
+    #[test]
+    fn pass_without_stopping() {
+        let (sut, stops) = sut();
+
+        assert!(update(&sut, between(&stops[1], &stops[2], 0.5), 0, 30).is_empty());
+        // Slowly through the stop, it might have stopped yet.
+        assert!(!update(&sut, between(&stops[2], &stops[3], 0.005), 30, 30)
+            .contains(&format!("passed {}", stops[2].name)));
+        // Several stops at once, e.g. after lost locations.
+        let events = update(&sut, between(&stops[4], &stops[5], 0.5), 60, 30);
+        assert_eq!(
+            vec![
+                format!("passed {}", stops[2].name),
+                format!("passed {}", stops[3].name),
+                format!("passed {}", stops[4].name),
+            ],
+            events[..3]
+        );
+    }
+
+    #[test]
+    fn standing_despite_speed() {
+        let (sut, stops) = sut();
+        let stop = stops[2].coordinates;
+
+        assert_eq!(
+            vec![format!("approaching {}", stops[2].name)],
+            update(&sut, stop, 0, 20)
+        );
+        assert_eq!(
+            vec![format!("arrived {}", stops[2].name)],
+            update(&sut, stop, 30, 20)
+        );
+        assert!(update(&sut, stop, 60, 0).is_empty());
+
+        let events = update(&sut, stops[3].coordinates, 90, 0);
+        assert_eq!(
+            vec![
+                format!("departed {} after 30s", stops[2].name),
+                format!("arrived {}", stops[3].name),
+            ],
+            events
+        );
+
+        sut.forget("10-1152");
+        assert!(update(&sut, between(&stops[3], &stops[4], 0.5), 120, 30).is_empty());
+    }
+
+    #[test]
+    fn jitter_back_to_departed_stop() {
+        let (sut, stops) = sut();
+        let stop = stops[2].coordinates;
+
+        update(&sut, between(&stops[1], &stops[2], 0.5), 0, 30);
+        update(&sut, stop, 30, 0);
+        update(&sut, stop, 60, 0);
+        let events = update(&sut, between(&stops[2], &stops[3], 0.3), 90, 25);
+        assert_eq!(format!("departed {} after 30s", stops[2].name), events[0]);
+
+        // Stuck in traffic with the location jumping back to the stop.
+        assert!(update(&sut, stop, 120, 0).is_empty());
+        assert!(update(&sut, stop, 150, 0).is_empty());
+        assert!(!update(&sut, between(&stops[2], &stops[3], 0.5), 180, 25)
+            .iter()
+            .any(|e| e.contains(&stops[2].name)));
+    }
+}
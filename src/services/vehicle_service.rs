@@ -0,0 +1,92 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::domain::{Coordinates, Location, Ride, RouteDirection};
+
+type CarLicense = String;
+
+/// The last known state of a bus, with everything the pipeline could match for it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Vehicle {
+    pub car_license: CarLicense,
+    pub date_time: NaiveDateTime,
+    /// Smoothed, see [`super::FilterService`].
+    pub coordinates: Coordinates,
+    /// As reported by the bus.
+    pub raw_coordinates: Coordinates,
+    pub speed: u32,
+    pub heading: f32,
+    pub altitude: u32,
+    pub operate_position: Option<String>,
+    /// The position is inferred from the track, not taken from the sheet.
+    pub position_inferred: bool,
+    pub ride: Option<Ride>,
+    pub direction: Option<RouteDirection>,
+    pub previous_stop: Option<String>,
+    pub next_stop: Option<String>,
+    /// Distance travelled along the route of the direction, meters.
+    pub route_distance: Option<f64>,
+    pub off_route: bool,
+    /// Deviation from the ride schedule at the last passed stop, seconds, positive when late.
+    pub delay: Option<i64>,
+}
+
+impl From<&Location> for Vehicle {
+    fn from(location: &Location) -> Self {
+        Self {
+            car_license: location.car_license.clone(),
+            date_time: location.date_time,
+            coordinates: location.coordinates,
+            raw_coordinates: location.coordinates,
+            speed: location.speed,
+            heading: location.heading.0,
+            altitude: location.altitude,
+            operate_position: None,
+            position_inferred: false,
+            ride: None,
+            direction: None,
+            previous_stop: None,
+            next_stop: None,
+            route_distance: None,
+            off_route: false,
+            delay: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VehicleService {
+    vehicles: RwLock<HashMap<CarLicense, Vehicle>>,
+}
+
+impl VehicleService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, vehicle: Vehicle) {
+        self.vehicles
+            .write()
+            .unwrap()
+            .insert(vehicle.car_license.clone(), vehicle);
+    }
+
+    pub fn get(&self, car_license: &str) -> Option<Vehicle> {
+        self.vehicles.read().unwrap().get(car_license).cloned()
+    }
+
+    /// All known vehicles, ordered by car license.
+    pub fn all(&self) -> Vec<Vehicle> {
+        let mut vehicles = self
+            .vehicles
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        vehicles.sort_by(|a, b| a.car_license.cmp(&b.car_license));
+        vehicles
+    }
+}
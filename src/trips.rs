@@ -0,0 +1,441 @@
+//! Trips stitched together from the locations of a bus, terminal to terminal.
+
+use chrono::{NaiveDateTime, TimeDelta};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::domain::{Coordinates, Network, Ride, Stop, Terminal};
+
+/// Distance from the terminal stop within which the bus is at the terminal, meters.
+const TERMINAL_RADIUS_M: f64 = 150.0;
+/// Distance from a stop within which the bus has visited it, meters.
+const STOP_RADIUS_M: f64 = 100.0;
+/// A bus staying at a terminal this long ends the trip even without turning back.
+const LAYOVER: TimeDelta = TimeDelta::minutes(3);
+/// Terminals on the way between the airport and Rawai, a bus staying there may be held in
+/// traffic, so only turning back ends the trip.
+const INTERMEDIATE: [Terminal; 2] = [Terminal::Kata, Terminal::Patong];
+/// How far a trip may depart from the scheduled departure of its ride.
+const MATCH_TOLERANCE: TimeDelta = TimeDelta::minutes(30);
+
+/// A run of the bus from one terminal to another.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trip {
+    pub car_license: String,
+    pub start: Terminal,
+    pub stop: Terminal,
+    /// The last location at the start terminal.
+    pub started_at: NaiveDateTime,
+    /// The first location at the destination terminal.
+    pub ended_at: NaiveDateTime,
+    /// Stops of the line the bus came by, in travel order.
+    pub stops: Vec<String>,
+    /// Meters.
+    pub distance: f64,
+    /// The position the bus drove the trip as, recorded with its locations.
+    pub position: Option<String>,
+    pub ride: Option<Ride>,
+}
+
+/// A scheduled ride no trip has been matched to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissedRide {
+    pub departure: NaiveDateTime,
+    pub ride: Ride,
+}
+
+/// Consecutive locations at a terminal, by their indices.
+#[derive(Debug, Clone, Copy)]
+struct Visit {
+    terminal: Terminal,
+    first: usize,
+    last: usize,
+}
+
+/// Splits the locations of the bus, ordered by time, into trips between the terminals where
+/// the bus has turned back or laid over. Runs before the first and after the last such
+/// terminal are incomplete and left out.
+pub fn reconstruct(
+    car_license: &str,
+    locations: &[(NaiveDateTime, Coordinates)],
+    stops: &[Stop],
+    network: &Network,
+) -> Vec<Trip> {
+    let terminals = [
+        Terminal::Airport,
+        Terminal::Rawai,
+        Terminal::Kata,
+        Terminal::Patong,
+    ]
+    .into_iter()
+    .filter_map(|t| {
+        let stop = stops.iter().find(|s| s.name == t.stop_name())?;
+        Some((t, stop.coordinates))
+    })
+    .collect::<Vec<_>>();
+    let at_terminal = |coordinates: Coordinates| {
+        terminals
+            .iter()
+            .find(|(_, c)| c.distance_to(coordinates) <= TERMINAL_RADIUS_M)
+            .map(|(t, _)| *t)
+    };
+
+    let mut visits: Vec<Visit> = vec![];
+    for (index, (_, coordinates)) in locations.iter().enumerate() {
+        let Some(terminal) = at_terminal(*coordinates) else {
+            continue;
+        };
+        match visits.last_mut() {
+            Some(visit) if visit.terminal == terminal && visit.last + 1 == index => {
+                visit.last = index;
+            }
+            _ => visits.push(Visit {
+                terminal,
+                first: index,
+                last: index,
+            }),
+        }
+    }
+
+    let ends = visits
+        .into_iter()
+        .filter(|visit| is_trip_end(locations, terminals.as_slice(), *visit))
+        .collect::<Vec<_>>();
+    ends.windows(2)
+        .map(|w| {
+            let (from, to) = (w[0], w[1]);
+            let run = &locations[from.last..=to.first];
+            let line_stops = network
+                .pattern(from.terminal, to.terminal)
+                .map(|p| p.route.stops().to_vec())
+                .unwrap_or_default();
+            Trip {
+                car_license: car_license.to_string(),
+                start: from.terminal,
+                stop: to.terminal,
+                started_at: run[0].0,
+                ended_at: run[run.len() - 1].0,
+                stops: line_stops
+                    .into_iter()
+                    .filter(|s| {
+                        run.iter()
+                            .any(|(_, c)| s.coordinates.distance_to(*c) <= STOP_RADIUS_M)
+                    })
+                    .map(|s| s.name)
+                    .collect(),
+                distance: run.windows(2).map(|w| w[0].1.distance_to(w[1].1)).sum(),
+                position: None,
+                ride: None,
+            }
+        })
+        .collect()
+}
+
+/// Whether the bus has ended a trip at the terminal: the locations run out, it has stayed
+/// for a layover at an end of the line, or it has come back the way it arrived.
+fn is_trip_end(
+    locations: &[(NaiveDateTime, Coordinates)],
+    terminals: &[(Terminal, Coordinates)],
+    visit: Visit,
+) -> bool {
+    if visit.first == 0 || visit.last + 1 == locations.len() {
+        return true;
+    }
+    if !INTERMEDIATE.contains(&visit.terminal)
+        && locations[visit.last].0 - locations[visit.first].0 >= LAYOVER
+    {
+        return true;
+    }
+    let Some((_, terminal)) = terminals.iter().find(|(t, _)| *t == visit.terminal) else {
+        return false;
+    };
+    let before = locations[visit.first - 1].1;
+    let after = locations[visit.last + 1].1;
+    // Passing through, the bus leaves on the far side of the terminal.
+    before.distance_to(after)
+        < f64::midpoint(before.distance_to(*terminal), terminal.distance_to(after))
+}
+
+/// Sets the position of every trip to the one most of its locations were matched to.
+pub fn assign_positions(trips: &mut [Trip], positions: &[(NaiveDateTime, Option<String>)]) {
+    for trip in trips {
+        trip.position = positions
+            .iter()
+            .filter(|(time, _)| (trip.started_at..=trip.ended_at).contains(time))
+            .filter_map(|(_, position)| position.as_ref())
+            .counts()
+            .into_iter()
+            .max_by_key(|(position, count)| (*count, std::cmp::Reverse(*position)))
+            .map(|(position, _)| position.clone());
+    }
+}
+
+/// Matches the trips to the rides of their position with the same terminals departing
+/// closest to the trip start, each ride to one trip at most. Returns the rides scheduled
+/// to depart within `from..=to` with no trip.
+pub fn match_rides(
+    trips: &mut [Trip],
+    rides: &[Ride],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Vec<MissedRide> {
+    let mut scheduled = from
+        .date()
+        .iter_days()
+        .take_while(|date| *date <= to.date())
+        .flat_map(|date| {
+            rides.iter().map(move |ride| MissedRide {
+                departure: date.and_time(ride.departure),
+                ride: ride.clone(),
+            })
+        })
+        .filter(|s| (from..=to).contains(&s.departure))
+        .collect::<Vec<_>>();
+
+    for trip in trips.iter_mut() {
+        let Some((index, _)) = scheduled
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| trip.position.as_ref() == Some(&s.ride.name))
+            .filter(|(_, s)| s.ride.start == trip.start && s.ride.stop == trip.stop)
+            .map(|(index, s)| (index, (trip.started_at - s.departure).abs()))
+            .filter(|(_, deviation)| *deviation <= MATCH_TOLERANCE)
+            .min_by_key(|(_, deviation)| *deviation)
+        else {
+            continue;
+        };
+        trip.ride = Some(scheduled.remove(index).ride);
+    }
+    scheduled
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::domain::{parse_list, Schedule, TEST_SCHEDULE, TEST_STOPS};
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn data() -> (Vec<Stop>, Vec<Ride>, Network) {
+        let stops = parse_list::<_, Stop>(TEST_STOPS).unwrap();
+        let schedule = parse_list::<_, Schedule>(TEST_SCHEDULE).unwrap();
+        let network = Network::build(&stops, schedule.iter().map(|s| (s.start, s.destination)));
+        let rides = schedule.into_iter().map(Ride::from).collect();
+        (stops, rides, network)
+    }
+
+    /// A location every two minutes at each stop of the line, from `at`.
+    fn drive(
+        network: &Network,
+        start: Terminal,
+        stop: Terminal,
+        at: NaiveDateTime,
+    ) -> Vec<(NaiveDateTime, Coordinates)> {
+        network
+            .pattern(start, stop)
+            .unwrap()
+            .route
+            .stops()
+            .iter()
+            .enumerate()
+            .map(|(index, s)| {
+                let index = i32::try_from(index).unwrap();
+                (at + TimeDelta::minutes(2) * index, s.coordinates)
+            })
+            .collect()
+    }
+
+    fn ride(name: &str, departure: NaiveTime, start: Terminal, stop: Terminal) -> Ride {
+        Ride {
+            name: name.to_string(),
+            start,
+            stop,
+            loading: departure,
+            departure,
+            arrival: departure,
+        }
+    }
+
+    #[test]
+    fn reconstruct_trips() {
+        let (stops, _, network) = data();
+        let mut locations = drive(&network, Terminal::Airport, Terminal::Rawai, time(15, 0));
+        let back = time(16, 30);
+        // Turning back at Rawai right away.
+        locations.extend(drive(&network, Terminal::Rawai, Terminal::Airport, back).split_off(1));
+
+        let trips = reconstruct("10-1152", &locations, &stops, &network);
+
+        assert_eq!(2, trips.len());
+        let trip = &trips[0];
+        assert_eq!(
+            (Terminal::Airport, Terminal::Rawai),
+            (trip.start, trip.stop)
+        );
+        assert_eq!(time(15, 0), trip.started_at);
+        assert_eq!(time(15, 52), trip.ended_at);
+        assert_eq!(27, trip.stops.len());
+        assert!(trip.distance > 40_000.0);
+        assert_eq!(
+            (Terminal::Rawai, Terminal::Airport),
+            (trips[1].start, trips[1].stop)
+        );
+        assert_eq!(time(15, 52), trips[1].started_at);
+    }
+
+    #[test]
+    fn pass_through_terminal() {
+        let (stops, _, network) = data();
+        // Patong is on the way from the airport to Rawai.
+        let mut locations = drive(&network, Terminal::Airport, Terminal::Rawai, time(15, 0));
+        locations.extend(drive(
+            &network,
+            Terminal::Rawai,
+            Terminal::Airport,
+            time(16, 30),
+        ));
+
+        let trips = reconstruct("10-1152", &locations, &stops, &network);
+
+        assert_eq!(
+            vec![
+                (Terminal::Airport, Terminal::Rawai),
+                (Terminal::Rawai, Terminal::Airport)
+            ],
+            trips.iter().map(|t| (t.start, t.stop)).collect::<Vec<_>>()
+        );
+        // The layover at Rawai.
+        assert_eq!(time(16, 30), trips[1].started_at);
+    }
+
+    #[test]
+    fn held_at_kata() {
+        let (stops, _, network) = data();
+        let mut locations = drive(&network, Terminal::Airport, Terminal::Rawai, time(15, 0));
+        let kata = stops
+            .iter()
+            .find(|s| s.name == Terminal::Kata.stop_name())
+            .unwrap()
+            .coordinates;
+        let at = locations
+            .iter()
+            .position(|(_, c)| c.distance_to(kata) <= TERMINAL_RADIUS_M)
+            .unwrap();
+        // Five minutes in the traffic by the terminal.
+        let held = TimeDelta::minutes(5);
+        for location in &mut locations[at + 1..] {
+            location.0 += held;
+        }
+        let (time, coordinates) = locations[at];
+        locations.insert(at + 1, (time + held, coordinates));
+
+        let trips = reconstruct("10-1152", &locations, &stops, &network);
+
+        assert_eq!(
+            vec![(Terminal::Airport, Terminal::Rawai)],
+            trips.iter().map(|t| (t.start, t.stop)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn match_trips_to_rides() {
+        let (stops, _, network) = data();
+        let mut locations = drive(&network, Terminal::Airport, Terminal::Rawai, time(15, 5));
+        locations.extend(drive(
+            &network,
+            Terminal::Rawai,
+            Terminal::Airport,
+            time(18, 0),
+        ));
+        let mut trips = reconstruct("10-1152", &locations, &stops, &network);
+        let positions = locations
+            .iter()
+            .map(|(time, _)| (*time, Some("Bus7".to_string())))
+            .collect::<Vec<_>>();
+        assign_positions(&mut trips, &positions);
+        let rides = [
+            ride(
+                "Bus7",
+                NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+                Terminal::Airport,
+                Terminal::Rawai,
+            ),
+            ride(
+                "Bus7",
+                NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+                Terminal::Rawai,
+                Terminal::Airport,
+            ),
+            ride(
+                "Bus7",
+                NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+                Terminal::Airport,
+                Terminal::Rawai,
+            ),
+            ride(
+                "Bus7",
+                NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+                Terminal::Rawai,
+                Terminal::Airport,
+            ),
+        ];
+
+        let missed = match_rides(&mut trips, &rides, time(14, 0), time(20, 0));
+
+        assert_eq!(Some(&rides[0]), trips[0].ride.as_ref());
+        // An hour later than scheduled.
+        assert_eq!(None, trips[1].ride);
+        assert_eq!(
+            vec![time(17, 0), time(19, 0)],
+            missed.iter().map(|m| m.departure).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn match_rides_of_recorded_position() {
+        let (stops, _, network) = data();
+        let locations = drive(&network, Terminal::Airport, Terminal::Rawai, time(15, 0));
+        let mut trips = reconstruct("10-1152", &locations, &stops, &network);
+        // Reassigned from Bus8 to Bus7 at the start of the trip.
+        let positions = locations
+            .iter()
+            .enumerate()
+            .map(|(index, (time, _))| {
+                let position = if index < 2 { "Bus8" } else { "Bus7" };
+                (*time, Some(position.to_string()))
+            })
+            .collect::<Vec<_>>();
+        assign_positions(&mut trips, &positions);
+        let rides = [
+            ride(
+                "Bus8",
+                NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+                Terminal::Airport,
+                Terminal::Rawai,
+            ),
+            ride(
+                "Bus7",
+                NaiveTime::from_hms_opt(15, 10, 0).unwrap(),
+                Terminal::Airport,
+                Terminal::Rawai,
+            ),
+        ];
+
+        let missed = match_rides(&mut trips, &rides, time(14, 0), time(16, 0));
+
+        assert_eq!(Some("Bus7"), trips[0].position.as_deref());
+        assert_eq!(Some(&rides[1]), trips[0].ride.as_ref());
+        assert_eq!(
+            vec![rides[0].clone()],
+            missed.into_iter().map(|m| m.ride).collect::<Vec<_>>()
+        );
+    }
+}
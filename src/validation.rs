@@ -0,0 +1,117 @@
+use serde::Serialize;
+
+use crate::{
+    data_source::{DataSource, Dataset},
+    domain::{parse_rows, Aliases, Bus, FromRow, Rejection, Schedule, Stop},
+};
+
+/// The rows of a sheet left out while parsing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SheetReport {
+    pub dataset: Dataset,
+    pub parsed: usize,
+    pub rejections: Vec<Rejection>,
+}
+
+/// Reads the sheet from the source, reporting the rejected rows.
+pub fn parse_sheet<T: FromRow>(
+    source: &dyn DataSource,
+    dataset: Dataset,
+    aliases: &Aliases,
+) -> anyhow::Result<(Vec<T>, SheetReport)> {
+    let (list, rejections) = parse_rows(source.open(dataset)?, aliases)
+        .map_err(|err| err.context(format!("{dataset:?}")))?;
+    let report = SheetReport {
+        dataset,
+        parsed: list.len(),
+        rejections,
+    };
+    Ok((list, report))
+}
+
+/// Validates all the sheets of the source.
+pub fn validate(source: &dyn DataSource, aliases: &Aliases) -> anyhow::Result<Vec<SheetReport>> {
+    Ok(vec![
+        parse_sheet::<Bus>(source, Dataset::Buses, aliases)?.1,
+        parse_sheet::<Schedule>(source, Dataset::Schedule, aliases)?.1,
+        parse_sheet::<Stop>(source, Dataset::Stops, aliases)?.1,
+    ])
+}
+
+/// Fails when a sheet has more rejected rows than allowed.
+pub fn check(reports: &[SheetReport], max_rejected_rows: Option<usize>) -> anyhow::Result<()> {
+    let Some(max) = max_rejected_rows else {
+        return Ok(());
+    };
+    for report in reports {
+        anyhow::ensure!(
+            report.rejections.len() <= max,
+            "{:?}: {} rows rejected, at most {max} allowed",
+            report.dataset,
+            report.rejections.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::data_source::MemorySource;
+
+    use super::*;
+
+    #[test]
+    fn rejections() {
+        let source = MemorySource {
+            stops: serde_json::json!({
+                "range": "",
+                "majorDimension": "ROWS",
+                "values": [
+                    ["order", "stop_name_th", "stop_name_eng", "description", "route_direction", "lng", "lat", "times", "icon", "color", "Bus stop Unique ID", "Image bus stop", "direction Link", "Display"],
+                    ["1", "ท่าอากาศยานภูเก็ต", "Phuket Airport", "", "Airport --> Rawai", "8.10846", "98.30655", "", "", "", "", "", "", "on"],
+                    ["2", "", "Typo", "", "Airport --> Rawai", "8.1O", "98.3", "", "", "", "", "", "", "on"],
+                    ["3", "", "Short row"],
+                ],
+            })
+            .to_string()
+            .into_bytes(),
+            ..MemorySource::embedded()
+        };
+
+        let (stops, report) =
+            parse_sheet::<Stop>(&source, Dataset::Stops, &Aliases::new()).unwrap();
+
+        assert_eq!(1, stops.len());
+        assert_eq!(1, report.parsed);
+        assert_eq!(
+            vec![
+                Rejection {
+                    row: 3,
+                    column: Some("lng".to_string()),
+                    value: "8.1O".to_string(),
+                    reason: "invalid float literal".to_string(),
+                },
+                Rejection {
+                    row: 4,
+                    column: Some("route_direction".to_string()),
+                    value: String::new(),
+                    reason: "missing route direction".to_string(),
+                },
+            ],
+            report.rejections
+        );
+    }
+
+    #[rstest]
+    #[case(None, true)]
+    #[case(Some(3), true)]
+    #[case(Some(2), false)]
+    fn check_threshold(#[case] max_rejected_rows: Option<usize>, #[case] ok: bool) {
+        let reports = validate(&MemorySource::embedded(), &Aliases::new()).unwrap();
+        assert_eq!(3, reports[0].rejections.len());
+
+        assert_eq!(ok, check(&reports, max_rejected_rows).is_ok());
+    }
+}
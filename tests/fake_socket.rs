@@ -0,0 +1,77 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn lines<R: Read + Send + 'static>(input: R) -> Receiver<String> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(input).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+fn wait_for(rx: &Receiver<String>, timeout: Duration, predicate: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(left) {
+            Ok(line) if predicate(&line) => return line,
+            Ok(_) => {}
+            Err(err) => panic!("Expected line not found: {err}"),
+        }
+    }
+}
+
+#[test]
+fn client_receives_fixture_through_socket() {
+    // Kept until the client is killed, separate for runs at the same time.
+    let dir = tempfile::tempdir().expect("Temp dir created");
+    let mut server = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_fake_socket"))
+            .args(["data/locations.jsonl", "--interval-ms", "10"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Server started"),
+    );
+    let server_out = lines(server.0.stdout.take().unwrap());
+    let addr = wait_for(&server_out, Duration::from_secs(10), |l| {
+        l.starts_with("Listening on ")
+    })
+    .trim_start_matches("Listening on ")
+    .to_string();
+
+    let mut client = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_smart-bus-phuket"))
+            .env("SMART_BUS_APP_SOCKET", format!("http://{addr}"))
+            // Offline, with the data snapshot of the repository.
+            .env("SMART_BUS_DATA_SOURCE", "embedded")
+            .env("SMART_BUS_HISTORY_DB", dir.path().join("history.sqlite"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Client started"),
+    );
+    let client_err = lines(client.0.stderr.take().unwrap());
+
+    // The last fixture entry belongs to a bus that is never in the sheet.
+    wait_for(&client_err, Duration::from_mins(1), |l| {
+        l.contains("Non-operating bus, license=99-9999")
+    });
+}